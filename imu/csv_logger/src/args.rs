@@ -15,6 +15,22 @@ pub struct Args {
     #[arg(long, default_value_t = super::DEFAULT_VID_PID.1)]
     pub pid: u16,
 
+    /// usb serial number of the board, to choose among more connected boards
+    #[arg(long)]
+    pub serial_number: Option<String>,
+
+    /// list connected boards and exit
+    #[arg(long)]
+    pub list: bool,
+
+    /// reboot the board into USB bootloader for firmware update and exit
+    #[arg(long)]
+    pub bootloader: bool,
+
+    /// attach to the running stream, the board is neither restarted nor configured
+    #[arg(long)]
+    pub attach: bool,
+
     /// baudrate to be used for port
     #[arg(long, default_value_t = super::DEFAULT_BAUDRATE)]
     pub baud_rate: u32,
@@ -0,0 +1,333 @@
+use std::collections::VecDeque;
+use std::future::{poll_fn, Future};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+
+use futures_core::Stream;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio_serial::SerialPortBuilderExt;
+
+use super::{
+    burst_data, decode, find_port, protocol, AdisDeviceError, AdisDeviceResult, AdisVersion,
+    DecodeErrors, Duration, SensorBurst, MAX_MESSAGE_LEN, RESPONSE_TIMEOUT, STORE_TIMEOUT,
+};
+
+/// Asynchronous counterpart of `AdisDevice`, works on top of any tokio byte stream.
+pub struct AsyncAdisDevice<T = tokio_serial::SerialStream> {
+    port: T,
+    buffer: protocol::CobsAccumulator<MAX_MESSAGE_LEN>,
+    version: AdisVersion,
+    received: VecDeque<protocol::Message>,
+    /// Bursts of a batch not taken yet.
+    bursts: VecDeque<SensorBurst>,
+    decode_errors: DecodeErrors,
+}
+
+impl AsyncAdisDevice {
+    pub fn from_device_name<S: Into<String>, B: Into<u32>>(
+        path: S,
+        baud_rate: B,
+        version: AdisVersion,
+    ) -> AdisDeviceResult<Self> {
+        let port = tokio_serial::new(path.into(), baud_rate.into())
+            .open_native_async()
+            .map_err(io::Error::from)?;
+
+        return Ok(Self::new(port, version));
+    }
+
+    pub fn from_vid_pid(
+        vid: u16,
+        pid: u16,
+        baud_rate: u32,
+        version: AdisVersion,
+    ) -> AdisDeviceResult<Self> {
+        let path = find_port(vid, pid, None)?;
+        return Self::from_device_name(path, baud_rate, version);
+    }
+}
+
+impl<T: AsyncRead + AsyncWrite + Unpin> AsyncAdisDevice<T> {
+    pub fn new(port: T, version: AdisVersion) -> Self {
+        return Self {
+            port,
+            buffer: protocol::CobsAccumulator::new(),
+            version,
+            received: VecDeque::new(),
+            bursts: VecDeque::new(),
+            decode_errors: DecodeErrors::default(),
+        };
+    }
+
+    pub fn decode_errors(&self) -> DecodeErrors {
+        return self.decode_errors;
+    }
+
+    /// Polls for the next message from the device, decoded messages are kept until they are taken.
+    pub fn poll_receive(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<AdisDeviceResult<protocol::Message>> {
+        loop {
+            if let Some(message) = self.received.pop_front() {
+                return Poll::Ready(match message {
+                    protocol::Message::ERR(tag) => Err(AdisDeviceError::DeviceError(tag)),
+                    m => Ok(m),
+                });
+            }
+
+            let mut read_buffer = [0; MAX_MESSAGE_LEN];
+            let mut read_buf = ReadBuf::new(&mut read_buffer);
+            ready!(Pin::new(&mut self.port).poll_read(cx, &mut read_buf))?;
+
+            if read_buf.filled().is_empty() {
+                return Poll::Ready(Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()));
+            }
+
+            let received = &mut self.received;
+            decode(
+                &mut self.buffer,
+                &mut self.decode_errors,
+                read_buf.filled(),
+                |m| received.push_back(m),
+            );
+        }
+    }
+
+    /// Polls for the next burst, other messages are dropped.
+    pub fn poll_burst(&mut self, cx: &mut Context<'_>) -> Poll<AdisDeviceResult<SensorBurst>> {
+        loop {
+            if let Some(burst) = self.bursts.pop_front() {
+                return Poll::Ready(Ok(burst));
+            }
+
+            let message = ready!(self.poll_receive(cx))?;
+            self.bursts.extend(burst_data(&message, &self.version));
+        }
+    }
+
+    pub async fn receive(&mut self) -> AdisDeviceResult<protocol::Message> {
+        return poll_fn(|cx| self.poll_receive(cx)).await;
+    }
+
+    pub async fn send(&mut self, message: &protocol::Message) -> AdisDeviceResult<()> {
+        let write_buffer: heapless::Vec<_, MAX_MESSAGE_LEN> =
+            protocol::to_vec_cobs(message).map_err(AdisDeviceError::SerializationError)?;
+
+        self.port.write_all(&write_buffer).await?;
+        self.port.flush().await?;
+
+        return Ok(());
+    }
+
+    pub async fn confirmed_send(
+        &mut self,
+        message: &protocol::Message,
+        response_timeout: Option<Duration>,
+    ) -> AdisDeviceResult<()> {
+        self.send(message).await?;
+
+        return with_timeout(response_timeout, async {
+            loop {
+                if self.receive().await? == *message {
+                    return Ok(());
+                }
+            }
+        })
+        .await;
+    }
+
+    pub async fn send_request_response(
+        &mut self,
+        request: u16,
+        response_timeout: Option<Duration>,
+    ) -> AdisDeviceResult<u16> {
+        self.send(&protocol::Message::RQR(request)).await?;
+
+        return with_timeout(response_timeout, async {
+            loop {
+                if let protocol::Message::RQR(response) = self.receive().await? {
+                    return Ok(response);
+                }
+            }
+        })
+        .await;
+    }
+
+    /// Asks the device for its `Status` right away.
+    pub async fn send_status_request(
+        &mut self,
+        response_timeout: Option<Duration>,
+    ) -> AdisDeviceResult<protocol::Status> {
+        self.send(&protocol::Message::StatusRequest).await?;
+
+        return with_timeout(response_timeout, async {
+            loop {
+                if let protocol::Message::Status(status) = self.receive().await? {
+                    return Ok(status);
+                }
+            }
+        })
+        .await;
+    }
+
+    pub async fn send_restart(&mut self) -> AdisDeviceResult<()> {
+        return self
+            .confirmed_send(&protocol::Message::RST, Some(RESPONSE_TIMEOUT))
+            .await;
+    }
+
+    /// Configures the sensor 0.
+    pub async fn send_config(&mut self, config: protocol::cfg::CFG) -> AdisDeviceResult<()> {
+        return self.send_sensor_config(0, config).await;
+    }
+
+    pub async fn send_sensor_config(
+        &mut self,
+        sensor: protocol::SensorIndex,
+        config: protocol::cfg::CFG,
+    ) -> AdisDeviceResult<()> {
+        return self
+            .confirmed_send(
+                &protocol::Message::CFG(sensor, config),
+                Some(RESPONSE_TIMEOUT),
+            )
+            .await;
+    }
+
+    pub async fn send_error(&mut self, tag: u8) -> AdisDeviceResult<()> {
+        return self
+            .confirmed_send(&protocol::Message::ERR(tag), Some(RESPONSE_TIMEOUT))
+            .await;
+    }
+
+    /// Saves, loads or erases configuration stored in the board, the stored one is applied at power up.
+    pub async fn send_store(&mut self, store: protocol::Store) -> AdisDeviceResult<()> {
+        return self
+            .confirmed_send(&protocol::Message::STO(store), Some(STORE_TIMEOUT))
+            .await;
+    }
+
+    /// Sets SPI clock and stall time of the sensor, returns the timing applied with the frequency the board achieved.
+    ///
+    /// Rejected timing is handled as by `AdisDevice::send_spi_timing`.
+    pub async fn send_spi_timing(
+        &mut self,
+        sensor: protocol::SensorIndex,
+        timing: protocol::SpiTiming,
+    ) -> AdisDeviceResult<protocol::SpiTiming> {
+        if !timing.is_valid() {
+            return Err(AdisDeviceError::InvalidSpiTiming);
+        }
+
+        self.send(&protocol::Message::SpiTiming(sensor, timing))
+            .await?;
+
+        return with_timeout(Some(RESPONSE_TIMEOUT), async {
+            loop {
+                if let protocol::Message::SpiTiming(s, applied) = self.receive().await? {
+                    if s == sensor {
+                        return Ok(applied);
+                    }
+                }
+            }
+        })
+        .await;
+    }
+
+    /// Reboots the board into the USB bootloader, the device disappears and the board shows up as a drive.
+    pub async fn send_enter_bootloader(&mut self) -> AdisDeviceResult<()> {
+        return self
+            .confirmed_send(&protocol::Message::EnterBootloader, Some(RESPONSE_TIMEOUT))
+            .await;
+    }
+
+    /// Waits for the next burst, other messages are dropped.
+    pub async fn expect_burst(&mut self) -> AdisDeviceResult<SensorBurst> {
+        return poll_fn(|cx| self.poll_burst(cx)).await;
+    }
+
+    /// Stream of bursts of all sensors, data are read from the port only when the stream is polled,
+    /// so a slow consumer leaves them waiting in the OS buffer.
+    pub fn bursts(&mut self) -> BurstStream<'_, T> {
+        return BurstStream { device: self };
+    }
+}
+
+/// Stream of bursts from `AsyncAdisDevice`, ends when the device disconnects.
+pub struct BurstStream<'a, T> {
+    device: &'a mut AsyncAdisDevice<T>,
+}
+
+impl<'a, T: AsyncRead + AsyncWrite + Unpin> Stream for BurstStream<'a, T> {
+    type Item = AdisDeviceResult<SensorBurst>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let device = &mut *self.get_mut().device;
+        return match ready!(device.poll_burst(cx)) {
+            Err(AdisDeviceError::IoError(e)) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                Poll::Ready(None)
+            }
+            r => Poll::Ready(Some(r)),
+        };
+    }
+}
+
+async fn with_timeout<R, F>(timeout: Option<Duration>, future: F) -> AdisDeviceResult<R>
+where
+    F: Future<Output = AdisDeviceResult<R>>,
+{
+    return match timeout {
+        Some(t) => tokio::time::timeout(t, future)
+            .await
+            .unwrap_or(Err(AdisDeviceError::NoResponse)),
+        None => future.await,
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::AsyncReadExt;
+
+    const VERSION: AdisVersion = AdisVersion::ADIS16505_1BMLZ;
+
+    #[tokio::test]
+    async fn commands_and_burst_stream() {
+        let (port, mut device) = tokio::io::duplex(1024);
+        let mut adis = AsyncAdisDevice::new(port, VERSION);
+
+        let device = tokio::spawn(async move {
+            // echo the restart, then send few bursts and hang up
+            let mut rcv_buf = [0; 64];
+            let rcv_size = device.read(&mut rcv_buf).await.unwrap();
+            device.write_all(&rcv_buf[..rcv_size]).await.unwrap();
+
+            for sensor in 0..3 {
+                let burst = protocol::Message::B16(
+                    sensor % 2,
+                    protocol::cfg::BurstSel::Sel0,
+                    Default::default(),
+                );
+                let frame: heapless::Vec<u8, 64> = protocol::to_vec_cobs(&burst).unwrap();
+                device.write_all(&frame).await.unwrap();
+            }
+        });
+
+        adis.confirmed_send(&protocol::Message::RST, Some(Duration::from_secs(1)))
+            .await
+            .unwrap();
+
+        let mut bursts = adis.bursts();
+        let mut sensors = Vec::new();
+        while let Some(burst) = poll_fn(|cx| Pin::new(&mut bursts).poll_next(cx)).await {
+            let burst = burst.unwrap();
+            assert!(!burst.data.corrupted);
+            sensors.push(burst.sensor);
+        }
+
+        assert_eq!(sensors, [0, 1, 0]);
+        device.await.unwrap();
+    }
+}
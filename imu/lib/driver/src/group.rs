@@ -0,0 +1,677 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use serialport5 as serialport;
+
+use super::{
+    burst_data, protocol, AdisDevice, AdisDeviceResult, AdisVersion, Duration, SensorBurst,
+    Transport,
+};
+
+use protocol::adis::BurstData;
+use protocol::cfg::{SyncMode, CFG};
+
+/// Sample period of the sensor with default decimation.
+pub const DEFAULT_SAMPLE_PERIOD: Duration = Duration::from_micros(500);
+
+/// Sensor of every device the group configures and reads, the other sensors on the boards are left alone.
+const GROUP_SENSOR: protocol::SensorIndex = 0;
+
+/// Timestamped bursts of every device the board clocks are estimated from before the alignment.
+const ALIGN_TIMESTAMPS: usize = 64;
+
+/// Samples a device may lag behind the most advanced one before it is taken as silent and its samples are
+/// given up, 200 ms at the default sample period, well over the batching and USB latency.
+const MAX_LAG_SAMPLES: i64 = 400;
+
+/// How long the readers wait for data before checking whether they should stop.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Bursts of all devices taken at the same sync pulse, `None` for devices that missed it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupSample {
+    /// Sample number counted by the master device.
+    pub index: i64,
+    pub bursts: Vec<Option<BurstData>>,
+}
+
+/// Health of a single device in the group.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeviceStats {
+    pub received: u64,
+    pub corrupted: u64,
+    /// Bursts with any DIAG_STAT bit set.
+    pub diag_faults: u64,
+    /// Places where `data_cntr` skipped some samples.
+    pub gaps: u64,
+    /// Samples missing in the emitted tuples.
+    pub missing: u64,
+    pub errors: u64,
+    pub connected: bool,
+}
+
+type Received = (usize, Instant, AdisDeviceResult<SensorBurst>);
+
+/// Devices sampling together, the first one drives the SYNC pins of the others.
+///
+/// SYNC pins of the sensors have to be wired together. The master runs in `SyncMode::Output`,
+/// the other devices in `SyncMode::DirectInput`, so every pulse produces one burst on every device.
+/// Bursts are matched by `data_cntr`, the offsets between the counters are estimated once
+/// from the board timestamps of the first bursts. Board clocks are related to the host clock
+/// by the fastest deliveries, so USB and batching latency do not shift the estimate.
+/// Only the sensor 0 of every device is used.
+pub struct ImuGroup<T = serialport::SerialPort> {
+    stop: Arc<AtomicBool>,
+    readers: Vec<JoinHandle<AdisDevice<T>>>,
+    received: mpsc::Receiver<Received>,
+    aligner: Aligner,
+}
+
+impl ImuGroup {
+    /// Opens the boards with given USB serial numbers, the first one becomes the sync master.
+    pub fn from_serial_numbers(
+        serial_numbers: &[&str],
+        baud_rate: u32,
+        version: AdisVersion,
+        config: &[CFG],
+    ) -> AdisDeviceResult<Self> {
+        let devices = serial_numbers
+            .iter()
+            .map(|s| AdisDevice::from_serial_number(s, baud_rate, version, None))
+            .collect::<AdisDeviceResult<Vec<_>>>()?;
+
+        return Self::new(devices, config, DEFAULT_SAMPLE_PERIOD);
+    }
+}
+
+impl<T: Transport + Send + 'static> ImuGroup<T> {
+    /// Restarts and configures all devices identically and starts reading them, the first one is the sync master.
+    ///
+    /// `config` should not contain `BurstEn` or `SyncMode`, they are set by the group.
+    pub fn new(
+        mut devices: Vec<AdisDevice<T>>,
+        config: &[CFG],
+        sample_period: Duration,
+    ) -> AdisDeviceResult<Self> {
+        for (i, device) in devices.iter_mut().enumerate() {
+            device.send_restart()?;
+            for cfg in config {
+                device.send_sensor_config(GROUP_SENSOR, *cfg)?;
+            }
+            let sync_mode = if i == 0 {
+                SyncMode::Output
+            } else {
+                SyncMode::DirectInput
+            };
+            device.send_sensor_config(GROUP_SENSOR, CFG::SyncMode(sync_mode))?;
+        }
+
+        // slaves first, they wait for the pulses of the master
+        for device in devices.iter_mut().rev() {
+            device.send_sensor_config(GROUP_SENSOR, CFG::BurstEn(true))?;
+        }
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let (sender, received) = mpsc::channel();
+        let aligner = Aligner::new(devices.len(), sample_period);
+
+        let readers = devices
+            .into_iter()
+            .enumerate()
+            .map(|(i, device)| {
+                let stop = Arc::clone(&stop);
+                let sender = sender.clone();
+                thread::spawn(move || read_loop(i, device, &stop, &sender))
+            })
+            .collect();
+
+        return Ok(Self {
+            stop,
+            readers,
+            received,
+            aligner,
+        });
+    }
+
+    /// Stops reading and gives the devices back.
+    pub fn stop(mut self) -> Vec<AdisDevice<T>> {
+        self.stop.store(true, Ordering::Relaxed);
+        return self
+            .readers
+            .drain(..)
+            .map(|r| match r.join() {
+                Ok(device) => device,
+                Err(e) => std::panic::resume_unwind(e),
+            })
+            .collect();
+    }
+}
+
+impl<T> ImuGroup<T> {
+    /// Waits at most `timeout` for next aligned sample.
+    pub fn next_sample(&mut self, timeout: Duration) -> Option<GroupSample> {
+        let deadline = Instant::now() + timeout;
+
+        loop {
+            if let Some(sample) = self.aligner.pop() {
+                return Some(sample);
+            }
+
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match self.received.recv_timeout(timeout) {
+                Ok((device, time, burst)) => self.aligner.push(device, time, burst),
+                Err(mpsc::RecvTimeoutError::Timeout) => return None,
+                // all readers ended, whatever is left is final
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    self.aligner.flush();
+                    return self.aligner.pop();
+                }
+            }
+        }
+    }
+
+    pub fn stats(&self) -> &[DeviceStats] {
+        return &self.aligner.stats;
+    }
+}
+
+impl<T> Drop for ImuGroup<T> {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        for reader in self.readers.drain(..) {
+            reader.join().ok();
+        }
+    }
+}
+
+fn read_loop<T: Transport>(
+    index: usize,
+    mut device: AdisDevice<T>,
+    stop: &AtomicBool,
+    sender: &mpsc::Sender<Received>,
+) -> AdisDevice<T> {
+    while !stop.load(Ordering::Relaxed) {
+        let received = device.receive_timeout(READ_TIMEOUT);
+        let time = Instant::now();
+
+        match received {
+            Ok(messages) => {
+                let bursts = messages.iter().flat_map(|m| burst_data(m, &device.version));
+                for burst in bursts.filter(|b| b.sensor == GROUP_SENSOR) {
+                    sender.send((index, time, Ok(burst))).ok();
+                }
+            }
+            Err(e) => {
+                let fatal = e.is_disconnect();
+                sender.send((index, time, Err(e))).ok();
+                if fatal {
+                    break;
+                }
+            }
+        }
+    }
+
+    return device;
+}
+
+/// Per device counter state.
+#[derive(Debug, Clone, Copy)]
+struct Counter {
+    last: u16,
+    index: i64,
+}
+
+/// Board clock of a device related to the host clock, in microseconds.
+#[derive(Debug, Clone, Copy)]
+struct BoardClock {
+    /// Smallest host reception time minus board time seen.
+    offset_us: i64,
+    /// Last board timestamp as sent and unwrapped.
+    last_raw: u32,
+    last_us: i64,
+    /// Counter index of the burst with the last timestamp.
+    index: i64,
+    timestamps: usize,
+}
+
+impl BoardClock {
+    fn new(host_us: i64, timestamp_us: u32, index: i64) -> Self {
+        return Self {
+            offset_us: host_us - timestamp_us as i64,
+            last_raw: timestamp_us,
+            last_us: timestamp_us as i64,
+            index,
+            timestamps: 1,
+        };
+    }
+
+    fn update(&mut self, host_us: i64, timestamp_us: u32, index: i64) {
+        self.last_us += timestamp_us.wrapping_sub(self.last_raw) as i32 as i64;
+        self.last_raw = timestamp_us;
+        self.offset_us = self.offset_us.min(host_us - self.last_us);
+        self.index = index;
+        self.timestamps += 1;
+    }
+
+    /// Host time of the burst with the last timestamp.
+    fn host_us(&self) -> i64 {
+        return self.last_us + self.offset_us;
+    }
+}
+
+/// Matches bursts of the devices by their unwrapped sample counters.
+struct Aligner {
+    sample_period: Duration,
+    /// Host time the reception times are counted from.
+    epoch: Instant,
+    stats: Vec<DeviceStats>,
+    counters: Vec<Option<Counter>>,
+    /// Board clocks of the devices, until the offsets are known.
+    clocks: Vec<Option<BoardClock>>,
+    offsets: Option<Vec<i64>>,
+    /// Bursts waiting for alignment, before the offsets are known.
+    unaligned: Vec<(usize, i64, BurstData)>,
+    samples: BTreeMap<i64, Vec<Option<BurstData>>>,
+    /// Index of the last sample emitted, bursts coming later for it are dropped.
+    emitted: Option<i64>,
+    ready: VecDeque<GroupSample>,
+}
+
+impl Aligner {
+    fn new(devices: usize, sample_period: Duration) -> Self {
+        return Self {
+            sample_period,
+            epoch: Instant::now(),
+            stats: vec![
+                DeviceStats {
+                    connected: true,
+                    ..Default::default()
+                };
+                devices
+            ],
+            counters: vec![None; devices],
+            clocks: vec![None; devices],
+            offsets: None,
+            unaligned: Vec::new(),
+            samples: BTreeMap::new(),
+            emitted: None,
+            ready: VecDeque::new(),
+        };
+    }
+
+    fn pop(&mut self) -> Option<GroupSample> {
+        return self.ready.pop_front();
+    }
+
+    fn push(&mut self, device: usize, time: Instant, burst: AdisDeviceResult<SensorBurst>) {
+        let stats = &mut self.stats[device];
+        let (timestamp_us, burst) = match burst {
+            Ok(burst) => (burst.timestamp_us, burst.data),
+            Err(e) => {
+                stats.errors += 1;
+                if e.is_disconnect() {
+                    stats.connected = false;
+                    self.finalize();
+                }
+                return;
+            }
+        };
+
+        stats.received += 1;
+        if Into::<u16>::into(burst.diagstat) != 0 {
+            stats.diag_faults += 1;
+        }
+        // counter of corrupted burst can not be trusted
+        if burst.corrupted {
+            stats.corrupted += 1;
+            return;
+        }
+
+        let counter = match self.counters[device] {
+            None => Counter {
+                last: burst.data_cntr,
+                index: burst.data_cntr as i64,
+            },
+            Some(c) => {
+                let step = burst.data_cntr.wrapping_sub(c.last);
+                if step != 1 {
+                    stats.gaps += 1;
+                }
+                Counter {
+                    last: burst.data_cntr,
+                    index: c.index + step as i64,
+                }
+            }
+        };
+        self.counters[device] = Some(counter);
+
+        match &self.offsets {
+            Some(offsets) => {
+                let key = counter.index - offsets[device];
+                self.insert(device, key, burst);
+            }
+            None => {
+                if let Some(timestamp_us) = timestamp_us {
+                    let host_us = time.saturating_duration_since(self.epoch).as_micros() as i64;
+                    match self.clocks[device].as_mut() {
+                        Some(clock) => clock.update(host_us, timestamp_us, counter.index),
+                        None => {
+                            self.clocks[device] =
+                                Some(BoardClock::new(host_us, timestamp_us, counter.index))
+                        }
+                    }
+                }
+                self.unaligned.push((device, counter.index, burst));
+                self.align();
+            }
+        }
+        self.finalize();
+    }
+
+    /// Estimates offsets of the counters once the board clocks of all devices are known well enough.
+    fn align(&mut self) {
+        let Some(clocks) = self.clocks.iter().copied().collect::<Option<Vec<_>>>() else {
+            return;
+        };
+        if clocks.iter().any(|c| c.timestamps < ALIGN_TIMESTAMPS) {
+            return;
+        }
+
+        let master = clocks[0];
+        let period_us = self.sample_period.as_micros() as f64;
+        let offsets: Vec<i64> = clocks
+            .iter()
+            .map(|clock| {
+                let delay_us = (clock.host_us() - master.host_us()) as f64;
+                clock.index - master.index - (delay_us / period_us).round() as i64
+            })
+            .collect();
+
+        for (device, index, burst) in std::mem::take(&mut self.unaligned) {
+            self.insert(device, index - offsets[device], burst);
+        }
+        self.offsets = Some(offsets);
+    }
+
+    fn insert(&mut self, device: usize, key: i64, burst: BurstData) {
+        // the device was taken as silent, the sample went without it
+        if self.emitted.is_some_and(|emitted| key <= emitted) {
+            return;
+        }
+
+        let devices = self.stats.len();
+        self.samples
+            .entry(key)
+            .or_insert_with(|| vec![None; devices])[device] = Some(burst);
+    }
+
+    /// Emits samples no connected device can contribute to anymore, devices lagging more than `MAX_LAG_SAMPLES`
+    /// behind the others are left out.
+    fn finalize(&mut self) {
+        let Some(offsets) = &self.offsets else {
+            return;
+        };
+
+        let indices: Vec<i64> = self
+            .counters
+            .iter()
+            .zip(offsets)
+            .zip(&self.stats)
+            .filter(|(_, stats)| stats.connected)
+            .filter_map(|((counter, offset), _)| counter.map(|c| c.index - offset))
+            .collect();
+        let newest = indices.iter().copied().max();
+        let latest = indices
+            .into_iter()
+            .filter(|index| newest.is_some_and(|newest| newest - index <= MAX_LAG_SAMPLES))
+            .min();
+
+        match latest {
+            Some(latest) => {
+                let pending = self.samples.split_off(&(latest + 1));
+                let complete = std::mem::replace(&mut self.samples, pending);
+                self.emit(complete);
+            }
+            None => self.flush(),
+        }
+    }
+
+    fn flush(&mut self) {
+        let samples = std::mem::take(&mut self.samples);
+        self.emit(samples);
+    }
+
+    fn emit(&mut self, samples: BTreeMap<i64, Vec<Option<BurstData>>>) {
+        for (index, bursts) in samples {
+            self.emitted = Some(index);
+            for (stats, burst) in self.stats.iter_mut().zip(&bursts) {
+                if burst.is_none() {
+                    stats.missing += 1;
+                }
+            }
+            self.ready.push_back(GroupSample { index, bursts });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MemoryTransport;
+
+    const VERSION: AdisVersion = AdisVersion::ADIS16505_1BMLZ;
+
+    fn burst(cntr: u16) -> protocol::Message {
+        let mut words = [0; 10];
+        words[8] = cntr;
+        words[9] = (cntr >> 8) + (cntr & 0xff);
+        return protocol::Message::B16(0, protocol::cfg::BurstSel::Sel0, words.into());
+    }
+
+    /// Writes `(sample, data_cntr)` bursts one per batch, stamped by board clock started at `clock_us`.
+    fn write_bursts(
+        port: &mut MemoryTransport,
+        clock_us: u32,
+        period: Duration,
+        bursts: &[(u32, u16)],
+    ) {
+        for (sample, cntr) in bursts {
+            let timestamp_us = clock_us.wrapping_add(sample * period.as_micros() as u32);
+            let batch = protocol::Batch::new(burst(*cntr), timestamp_us).unwrap();
+            let frame: heapless::Vec<u8, 128> =
+                protocol::to_vec_cobs(&protocol::Message::BAT(batch)).unwrap();
+            port.write(&frame).unwrap();
+        }
+    }
+
+    fn counters(sample: &GroupSample) -> Vec<Option<u16>> {
+        return sample
+            .bursts
+            .iter()
+            .map(|b| b.map(|b| b.data_cntr))
+            .collect();
+    }
+
+    #[test]
+    fn aligns_by_counters() {
+        // loopback confirms the configuration, bursts are written into the other end
+        let ports = [MemoryTransport::loopback(), MemoryTransport::loopback()];
+        let (mut master, mut slave) = (ports[0].clone(), ports[1].clone());
+        let devices = ports.map(|p| AdisDevice::new(p, VERSION)).into();
+
+        let period = Duration::from_secs(1);
+        let mut group = ImuGroup::new(devices, &[], period).unwrap();
+        let devices = group.stats().len();
+
+        let samples = ALIGN_TIMESTAMPS as u32;
+        let master_bursts: Vec<_> = (0..samples).map(|s| (s, s as u16 + 1)).collect();
+        let slave_bursts: Vec<_> = (0..samples)
+            .map(|s| (s, (s as u16).wrapping_sub(1)))
+            .collect();
+        write_bursts(&mut master, 5_000, period, &master_bursts);
+        write_bursts(&mut slave, 3_000_000_000, period, &slave_bursts);
+        let mut aligned = Vec::new();
+        while let Some(sample) = group.next_sample(Duration::from_millis(50)) {
+            aligned.push(counters(&sample));
+        }
+        assert_eq!(aligned.len(), samples as usize);
+        assert_eq!(aligned[..2], [[Some(1), Some(65535)], [Some(2), Some(0)]]);
+
+        let next = samples as u16 + 1;
+        write_bursts(
+            &mut master,
+            5_000,
+            period,
+            &[
+                (samples, next),
+                (samples + 1, next + 1),
+                (samples + 2, next + 2),
+            ],
+        );
+        write_bursts(
+            &mut slave,
+            3_000_000_000,
+            period,
+            &[(samples, next - 2), (samples + 2, next)],
+        );
+        let mut aligned = Vec::new();
+        while let Some(sample) = group.next_sample(Duration::from_millis(50)) {
+            aligned.push(counters(&sample));
+        }
+        assert_eq!(
+            aligned,
+            [
+                [Some(next), Some(next - 2)],
+                [Some(next + 1), None],
+                [Some(next + 2), Some(next)]
+            ]
+        );
+
+        let stats = group.stats();
+        assert_eq!(stats.len(), devices);
+        let received = samples as u64 + 3;
+        assert_eq!(
+            (stats[0].received, stats[0].gaps, stats[0].missing),
+            (received, 0, 0)
+        );
+        assert_eq!(
+            (stats[1].received, stats[1].gaps, stats[1].missing),
+            (received - 1, 1, 1)
+        );
+        group.stop();
+    }
+
+    #[test]
+    fn aligns_jittered_deliveries_by_board_time() {
+        let period_us = DEFAULT_SAMPLE_PERIOD.as_micros() as u32;
+        let mut aligner = Aligner::new(2, DEFAULT_SAMPLE_PERIOD);
+
+        // boards started at different times, the clock of the slave wraps around meanwhile
+        let clocks = [1_000, u32::MAX - 40_000];
+        let first_cntr: [u16; 2] = [100, 60_000];
+        let mut seed: u32 = 1;
+        let mut deliveries = Vec::new();
+        for device in 0..2 {
+            let mut delivered_us = 0;
+            for first in (0..400).step_by(protocol::MAX_BATCH) {
+                // batch leaves with its last burst, USB delivers it up to 3 ms later, in order
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                let sent_us = (first + protocol::MAX_BATCH as u32 - 1) * period_us;
+                delivered_us = std::cmp::max(delivered_us, 10_000 + sent_us + (seed >> 16) % 3_000);
+
+                for sample in first..first + protocol::MAX_BATCH as u32 {
+                    let cntr = first_cntr[device].wrapping_add(sample as u16);
+                    let protocol::Message::B16(_, _, mem) = burst(cntr) else {
+                        unreachable!();
+                    };
+                    let burst = SensorBurst {
+                        sensor: 0,
+                        timestamp_us: (sample == first)
+                            .then(|| clocks[device].wrapping_add(sample * period_us)),
+                        data: protocol::adis::BurstData::as_sel0(&mem, &VERSION),
+                    };
+                    deliveries.push((delivered_us, device, burst));
+                }
+            }
+        }
+
+        deliveries.sort_by_key(|(delivered_us, _, _)| *delivered_us);
+        let epoch = aligner.epoch;
+        for (delivered_us, device, burst) in deliveries {
+            aligner.push(
+                device,
+                epoch + Duration::from_micros(delivered_us as u64),
+                Ok(burst),
+            );
+        }
+        aligner.flush();
+
+        let mut samples = 0;
+        while let Some(sample) = aligner.pop() {
+            let [Some(master), Some(slave)] = counters(&sample)[..] else {
+                panic!("incomplete sample {:?}", sample);
+            };
+            assert_eq!(
+                slave.wrapping_sub(master),
+                first_cntr[1].wrapping_sub(first_cntr[0])
+            );
+            samples += 1;
+        }
+        assert_eq!(samples, 400);
+    }
+
+    #[test]
+    fn silent_device_is_left_out() {
+        let period_us = DEFAULT_SAMPLE_PERIOD.as_micros() as u32;
+        let mut aligner = Aligner::new(2, DEFAULT_SAMPLE_PERIOD);
+        let epoch = aligner.epoch;
+        let push = |aligner: &mut Aligner, device, sample: u32| {
+            let protocol::Message::B16(_, _, mem) = burst(sample as u16) else {
+                unreachable!();
+            };
+            let burst = SensorBurst {
+                sensor: 0,
+                timestamp_us: Some(sample * period_us),
+                data: protocol::adis::BurstData::as_sel0(&mem, &VERSION),
+            };
+            let time = epoch + Duration::from_micros((sample * period_us) as u64);
+            aligner.push(device, time, Ok(burst));
+        };
+
+        let aligned = ALIGN_TIMESTAMPS as u32;
+        for sample in 0..aligned {
+            push(&mut aligner, 0, sample);
+            push(&mut aligner, 1, sample);
+        }
+
+        // the slave stays connected and stops sending, the master goes on
+        let last = aligned + MAX_LAG_SAMPLES as u32 + 10;
+        for sample in aligned..=last {
+            push(&mut aligner, 0, sample);
+        }
+        assert!(aligner.samples.is_empty());
+
+        let mut samples = Vec::new();
+        while let Some(sample) = aligner.pop() {
+            samples.push(counters(&sample));
+        }
+        assert_eq!(samples.len(), last as usize + 1);
+        assert_eq!(samples[aligned as usize - 1], [Some(aligned as u16 - 1); 2]);
+        assert_eq!(samples[aligned as usize], [Some(aligned as u16), None]);
+        assert_eq!(aligner.stats[1].missing, (last + 1 - aligned) as u64);
+
+        // burst of a sample gone already is dropped, the slave joins again with the next one
+        push(&mut aligner, 1, aligned);
+        push(&mut aligner, 1, last + 1);
+        push(&mut aligner, 0, last + 1);
+        assert_eq!(
+            aligner.pop().map(|s| counters(&s)),
+            Some(vec![Some(last as u16 + 1); 2])
+        );
+        assert!(aligner.pop().is_none());
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+
+use super::{burst_data, protocol, AdisDeviceError, AdisVersion, SensorBurst};
+
+type BurstHandler = Box<dyn FnMut(&SensorBurst) + Send>;
+type GapHandler = Box<dyn FnMut(SampleGap) + Send>;
+type ErrorHandler = Box<dyn FnMut(&AdisDeviceError) + Send>;
+type OverflowHandler = Box<dyn FnMut(protocol::OverflowSource, u32) + Send>;
+type HealthHandler = Box<dyn FnMut(protocol::SensorIndex, protocol::Health) + Send>;
+
+/// Missing samples detected from `data_cntr` of two consecutive bursts of the sensor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SampleGap {
+    pub sensor: protocol::SensorIndex,
+    pub previous: u16,
+    pub current: u16,
+    pub missing: u16,
+}
+
+/// Handlers registered on `AdisDevice`, called from whatever reads the device.
+#[derive(Default)]
+pub(crate) struct Handlers {
+    pub burst: Vec<BurstHandler>,
+    pub diag_fault: Vec<BurstHandler>,
+    pub sample_gap: Vec<GapHandler>,
+    pub disconnect: Vec<ErrorHandler>,
+    pub overflow: Vec<OverflowHandler>,
+    pub health: Vec<HealthHandler>,
+    /// Gaps are found by the owner of the device, `ResilientDevice` counts the bursts it hands out.
+    pub counted_by_owner: bool,
+    /// Last counter of every sensor.
+    last_cntr: HashMap<protocol::SensorIndex, u16>,
+}
+
+impl Handlers {
+    pub fn messages(&mut self, messages: &[protocol::Message], version: &AdisVersion) {
+        if self.burst.is_empty()
+            && self.diag_fault.is_empty()
+            && self.sample_gap.is_empty()
+            && self.overflow.is_empty()
+            && self.health.is_empty()
+        {
+            return;
+        }
+
+        for message in messages {
+            match message {
+                protocol::Message::Overflow { source, dropped } => {
+                    self.overflow.iter_mut().for_each(|h| h(*source, *dropped))
+                }
+                protocol::Message::Health(sensor, health) => {
+                    self.health.iter_mut().for_each(|h| h(*sensor, *health))
+                }
+                _ => {
+                    for burst in burst_data(message, version) {
+                        self.burst(&burst);
+                    }
+                }
+            }
+        }
+    }
+
+    fn burst(&mut self, burst: &SensorBurst) {
+        self.burst.iter_mut().for_each(|h| h(burst));
+
+        // nothing in corrupted burst can be trusted
+        if burst.data.corrupted {
+            return;
+        }
+
+        if Into::<u16>::into(burst.data.diagstat) != 0 {
+            self.diag_fault.iter_mut().for_each(|h| h(burst));
+        }
+
+        if self.counted_by_owner {
+            return;
+        }
+
+        let current = burst.data.data_cntr;
+        if let Some(previous) = self.last_cntr.insert(burst.sensor, current) {
+            let missing = current.wrapping_sub(previous).wrapping_sub(1);
+            if missing != 0 {
+                let gap = SampleGap {
+                    sensor: burst.sensor,
+                    previous,
+                    current,
+                    missing,
+                };
+                self.sample_gap(gap);
+            }
+        }
+    }
+
+    pub fn sample_gap(&mut self, gap: SampleGap) {
+        self.sample_gap.iter_mut().for_each(|h| h(gap));
+    }
+
+    pub fn error(&mut self, error: &AdisDeviceError) {
+        if error.is_disconnect() {
+            self.disconnect.iter_mut().for_each(|h| h(error));
+        }
+    }
+
+    /// Counter starts again after restart, that is not a gap.
+    pub fn restart(&mut self) {
+        self.last_cntr.clear();
+    }
+}
@@ -1,16 +1,54 @@
 pub use protocol;
 use serialport5 as serialport;
-use std::io::{Read, Write};
-use std::time::{SystemTime, SystemTimeError};
 pub use std::time::Duration;
+use std::time::{Instant, SystemTime, SystemTimeError};
 
 use heapless;
 use thiserror::Error;
 
 pub use protocol::adis::version::AdisVersion;
 
+pub mod transport;
+
+#[cfg(unix)]
+pub use transport::PtyTransport;
+pub use transport::{MemoryTransport, Transport};
+
+pub mod group;
+
+pub use group::{DeviceStats, GroupSample, ImuGroup};
+
+pub mod handlers;
+
+pub use handlers::SampleGap;
+
+pub mod reconnect;
+
+pub use reconnect::{Event, ResilientDevice};
+
+pub mod stream;
+
+pub use stream::{AdisStream, OverflowPolicy};
+
+#[cfg(feature = "async")]
+pub mod async_device;
+
+#[cfg(feature = "async")]
+pub use async_device::{AsyncAdisDevice, BurstStream};
+
 const MAX_MESSAGE_LEN: usize = 256;
 
+/// Bytes taken from the port in one read, enough for many bursts.
+const READ_BUFFER_LEN: usize = 4096;
+
+/// Answers of the board wait behind the bursts queued for the USB, configuration written into the sensor
+/// takes a few milliseconds more.
+const RESPONSE_TIMEOUT: Duration = Duration::from_millis(100);
+/// Erasing flash sector takes tens of milliseconds, up to 400 ms.
+const STORE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long `run` waits for data before asking whether to continue.
+const RUN_READ_TIMEOUT: Duration = Duration::from_millis(100);
 
 #[derive(Debug, Error)]
 pub enum AdisDeviceError {
@@ -28,16 +66,144 @@ pub enum AdisDeviceError {
     TimeError(#[from] SystemTimeError),
     #[error("Device sends error with tag: {0}.")]
     DeviceError(u8),
+    #[error("Device stopped sending heartbeats.")]
+    HeartbeatTimeout,
+    #[error("SPI timing is out of bounds.")]
+    InvalidSpiTiming,
     #[error("Unspecified error occurred.")]
     Other,
 }
 
+impl AdisDeviceError {
+    /// The port can not be used anymore, typically the device was unplugged.
+    pub fn is_disconnect(&self) -> bool {
+        return matches!(self, Self::IoError(_) | Self::PortError(_));
+    }
+}
+
 type AdisDeviceResult<T> = Result<T, AdisDeviceError>;
 
-pub struct AdisDevice {
-    port: serialport::SerialPort,
+/// Measured data of one burst together with the sensor it comes from.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SensorBurst {
+    pub sensor: protocol::SensorIndex,
+    /// Board time of the burst in microseconds, wraps around, only the first burst of a batch has it.
+    pub timestamp_us: Option<u32>,
+    pub data: protocol::adis::BurstData,
+}
+
+/// Received frames that could not be decoded, counted since the device was opened.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DecodeErrors {
+    /// Frames longer than the receive buffer.
+    pub overfull: u64,
+    /// Frames that were not valid messages.
+    pub deserialization: u64,
+}
+
+pub struct AdisDevice<T = serialport::SerialPort> {
+    port: T,
     buffer: protocol::CobsAccumulator<MAX_MESSAGE_LEN>,
     version: protocol::adis::version::AdisVersion,
+    decode_errors: DecodeErrors,
+    pending: Vec<protocol::Message>,
+    handlers: handlers::Handlers,
+    config: Vec<(protocol::SensorIndex, protocol::cfg::CFG)>,
+    spi_timing: Vec<(protocol::SensorIndex, protocol::SpiTiming)>,
+    status: Option<protocol::Status>,
+    heartbeat_timeout: Option<Duration>,
+    last_heartbeat: Instant,
+}
+
+impl<T: Transport> AdisDevice<T> {
+    pub fn new(port: T, version: AdisVersion) -> Self {
+        return Self {
+            port,
+            buffer: protocol::CobsAccumulator::new(),
+            version,
+            decode_errors: DecodeErrors::default(),
+            pending: Vec::new(),
+            handlers: handlers::Handlers::default(),
+            config: Vec::new(),
+            spi_timing: Vec::new(),
+            status: None,
+            heartbeat_timeout: None,
+            last_heartbeat: Instant::now(),
+        };
+    }
+
+    /// Configuration confirmed by the device since it was opened, last value of each kind.
+    pub fn applied_config(&self) -> &[(protocol::SensorIndex, protocol::cfg::CFG)] {
+        return &self.config;
+    }
+
+    /// SPI timing applied to each sensor since the device was opened, the last one of each sensor.
+    pub fn applied_spi_timing(&self) -> &[(protocol::SensorIndex, protocol::SpiTiming)] {
+        return &self.spi_timing;
+    }
+
+    pub fn decode_errors(&self) -> DecodeErrors {
+        return self.decode_errors;
+    }
+
+    /// Last `Status` the device sent, it sends one every second.
+    pub fn last_status(&self) -> Option<&protocol::Status> {
+        return self.status.as_ref();
+    }
+
+    /// Reading fails with `HeartbeatTimeout` whenever no `Status` comes for `timeout`, `None` turns it off.
+    pub fn set_heartbeat_timeout(&mut self, timeout: Option<Duration>) {
+        self.heartbeat_timeout = timeout;
+        self.last_heartbeat = Instant::now();
+    }
+
+    /// Called for every burst read from the device, corrupted ones included.
+    pub fn on_burst<F: FnMut(&SensorBurst) + Send + 'static>(&mut self, handler: F) {
+        self.handlers.burst.push(Box::new(handler));
+    }
+
+    /// Called for valid bursts with any DIAG_STAT bit set.
+    pub fn on_diag_fault<F: FnMut(&SensorBurst) + Send + 'static>(&mut self, handler: F) {
+        self.handlers.diag_fault.push(Box::new(handler));
+    }
+
+    /// Called when `data_cntr` of valid bursts of a sensor skips some samples.
+    pub fn on_sample_gap<F: FnMut(SampleGap) + Send + 'static>(&mut self, handler: F) {
+        self.handlers.sample_gap.push(Box::new(handler));
+    }
+
+    /// Called when the device reports messages it dropped and where, because they were not taken in time.
+    ///
+    /// Samples lost this way also show up as sample gaps, those without the report were lost in the sensor.
+    pub fn on_overflow<F: FnMut(protocol::OverflowSource, u32) + Send + 'static>(
+        &mut self,
+        handler: F,
+    ) {
+        self.handlers.overflow.push(Box::new(handler));
+    }
+
+    /// Called when the device reports health of a sensor, stuck sensors are reset by the device.
+    pub fn on_health<F: FnMut(protocol::SensorIndex, protocol::Health) + Send + 'static>(
+        &mut self,
+        handler: F,
+    ) {
+        self.handlers.health.push(Box::new(handler));
+    }
+
+    /// Called when reading fails because the device is gone.
+    pub fn on_disconnect<F: FnMut(&AdisDeviceError) + Send + 'static>(&mut self, handler: F) {
+        self.handlers.disconnect.push(Box::new(handler));
+    }
+
+    /// Reads the device until `keep_running` returns false, data are delivered only to handlers.
+    ///
+    /// Returns the first error, device errors included.
+    pub fn run<F: FnMut() -> bool>(&mut self, mut keep_running: F) -> AdisDeviceResult<()> {
+        while keep_running() {
+            self.receive_timeout(RUN_READ_TIMEOUT)?;
+        }
+        return Ok(());
+    }
 }
 
 impl AdisDevice {
@@ -52,11 +218,7 @@ impl AdisDevice {
             .read_timeout(timeout)
             .open(path.into())?;
 
-        Ok(Self {
-            port,
-            buffer: protocol::CobsAccumulator::new(),
-            version,
-        })
+        Ok(Self::new(port, version))
     }
 
     pub fn from_vid_pid(
@@ -66,59 +228,115 @@ impl AdisDevice {
         version: AdisVersion,
         timeout: Option<Duration>,
     ) -> AdisDeviceResult<Self> {
-        let port_name =
-            serialport::available_ports()?
-                .iter()
-                .find_map(|p| match p.port_type.clone() {
-                    serialport::SerialPortType::UsbPort(info) => {
-                        if (info.vid, info.pid) == (vid, pid) {
-                            Some(p.port_name.clone())
-                        } else {
-                            None
-                        }
-                    }
-                    _ => None,
-                });
+        let path = find_port(vid, pid, None)?;
+        return Self::from_device_name(path, baud_rate, version, timeout);
+    }
 
-        return if let Some(path) = port_name {
-            Self::from_device_name(path, baud_rate, version, timeout)
-        } else {
-            Err(AdisDeviceError::NoPort)
-        };
+    /// Opens the board with given USB serial number, see `list_devices`.
+    pub fn from_serial_number(
+        serial_number: &str,
+        baud_rate: u32,
+        version: AdisVersion,
+        timeout: Option<Duration>,
+    ) -> AdisDeviceResult<Self> {
+        let path = find_port(
+            protocol::VID_PID.0,
+            protocol::VID_PID.1,
+            Some(serial_number),
+        )?;
+        return Self::from_device_name(path, baud_rate, version, timeout);
     }
 }
 
-impl AdisDevice {
-    pub fn receive(&mut self) -> AdisDeviceResult<heapless::Vec<protocol::Message, 8>> {
-        let bytes_to_read = self.port.bytes_to_read()?;
-        let bytes_to_read = std::cmp::min(bytes_to_read as usize, MAX_MESSAGE_LEN);
+impl<T: Transport> AdisDevice<T> {
+    /// Reads whatever is available, messages received together with device error are kept for the next call.
+    pub fn receive(&mut self) -> AdisDeviceResult<Vec<protocol::Message>> {
+        let received = self.read(None)?;
+        return self.take_device_error(received);
+    }
 
-        if bytes_to_read == 0 {
-            return Ok(heapless::Vec::new());
-        };
+    /// Same as `receive`, but waits at most `timeout` for data to arrive.
+    pub fn receive_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> AdisDeviceResult<Vec<protocol::Message>> {
+        let received = self.read(Some(timeout))?;
+        return self.take_device_error(received);
+    }
+
+    /// Reads whatever is available and passes every decoded message, device errors included, to `sink`.
+    pub fn receive_with<F: FnMut(protocol::Message)>(
+        &mut self,
+        mut sink: F,
+    ) -> AdisDeviceResult<()> {
+        let received = self.read(None)?;
+        self.pending.drain(..).chain(received).for_each(&mut sink);
+
+        return Ok(());
+    }
 
-        let mut read_buffer = [0; MAX_MESSAGE_LEN];
-        let read_bytes = self.port.read(&mut read_buffer[..bytes_to_read])?;
+    /// Reads from the port and decodes, registered handlers see everything read.
+    fn read(&mut self, timeout: Option<Duration>) -> AdisDeviceResult<Vec<protocol::Message>> {
+        let mut read_buffer = vec![0; READ_BUFFER_LEN];
+        let read_bytes = match timeout {
+            Some(t) => self.port.read_timeout(&mut read_buffer, t),
+            None => self.port.read_available(&mut read_buffer),
+        };
 
-        let mut window = &read_buffer[..read_bytes];
+        let read_bytes = match read_bytes {
+            Ok(read_bytes) => read_bytes,
+            Err(e) => {
+                let error = AdisDeviceError::from(e);
+                self.handlers.error(&error);
+                return Err(error);
+            }
+        };
 
-        let mut out = heapless::Vec::new();
+        let mut received = Vec::new();
+        decode(
+            &mut self.buffer,
+            &mut self.decode_errors,
+            &read_buffer[..read_bytes],
+            |m| received.push(m),
+        );
+        self.handlers.messages(&received, &self.version);
 
-        'cobs: while !window.is_empty() {
-            window = match self.buffer.feed::<protocol::Message>(&window) {
-                protocol::FeedResult::Consumed => break 'cobs,
-                protocol::FeedResult::OverFull(new_wind) => new_wind,
-                protocol::FeedResult::DeserError(new_wind) => new_wind,
-                protocol::FeedResult::Success { data, remaining } => {
-                    if let protocol::Message::ERR(tag) = data {
-                        return Err(AdisDeviceError::DeviceError(tag));
-                    }
+        let status = received.iter().rev().find_map(|m| match m {
+            protocol::Message::Status(status) => Some(*status),
+            _ => None,
+        });
+        if status.is_some() {
+            self.status = status;
+            self.last_heartbeat = Instant::now();
+        } else if self
+            .heartbeat_timeout
+            .is_some_and(|t| self.last_heartbeat.elapsed() > t)
+        {
+            // reported once per timeout, what was read comes with the next call
+            self.last_heartbeat = Instant::now();
+            self.pending.extend(received);
+            return Err(AdisDeviceError::HeartbeatTimeout);
+        }
 
-                    out.push(data).ok();
+        return Ok(received);
+    }
 
-                    remaining
-                }
+    fn take_device_error(
+        &mut self,
+        received: Vec<protocol::Message>,
+    ) -> AdisDeviceResult<Vec<protocol::Message>> {
+        let mut out = std::mem::take(&mut self.pending);
+        out.extend(received);
+
+        if let Some(i) = out
+            .iter()
+            .position(|m| matches!(m, protocol::Message::ERR(_)))
+        {
+            let protocol::Message::ERR(tag) = out.remove(i) else {
+                unreachable!();
             };
+            self.pending = out;
+            return Err(AdisDeviceError::DeviceError(tag));
         }
 
         return Ok(out);
@@ -127,14 +345,15 @@ impl AdisDevice {
     pub fn send(&mut self, message: &protocol::Message) -> AdisDeviceResult<()> {
         let write_buffer: heapless::Vec<_, MAX_MESSAGE_LEN> =
             protocol::to_vec_cobs(message).map_err(|e| AdisDeviceError::SerializationError(e))?;
-            
+
         self.port.write(&write_buffer)?;
+        self.port.flush()?;
 
         return Ok(());
     }
 }
 
-impl AdisDevice {
+impl<T: Transport> AdisDevice<T> {
     pub fn confirmed_send(
         &mut self,
         message: &protocol::Message,
@@ -180,46 +399,507 @@ impl AdisDevice {
         return Err(AdisDeviceError::NoResponse);
     }
 
+    /// Asks the device for its `Status` right away.
+    pub fn send_status_request(
+        &mut self,
+        response_timeout: Option<Duration>,
+    ) -> AdisDeviceResult<protocol::Status> {
+        self.send(&protocol::Message::StatusRequest)?;
+        let start_time = SystemTime::now();
+
+        while response_timeout.is_none()
+            || start_time.elapsed()? < unsafe { response_timeout.unwrap_unchecked() }
+        {
+            let received_messages = self.receive()?;
+            for m in received_messages {
+                if let protocol::Message::Status(status) = m {
+                    return Ok(status);
+                }
+            }
+        }
+
+        return Err(AdisDeviceError::NoResponse);
+    }
+
     pub fn send_restart(&mut self) -> AdisDeviceResult<()> {
-        return self.confirmed_send(&protocol::Message::RST, Some(Duration::from_millis(1)));
+        self.confirmed_send(&protocol::Message::RST, Some(RESPONSE_TIMEOUT))?;
+        self.handlers.restart();
+        self.config.clear();
+        return Ok(());
     }
 
+    /// Configures the sensor 0.
     pub fn send_config(&mut self, config: protocol::cfg::CFG) -> AdisDeviceResult<()> {
-        return self.confirmed_send(
-            &protocol::Message::CFG(config),
-            Some(Duration::from_millis(1)),
-        );
+        return self.send_sensor_config(0, config);
+    }
+
+    pub fn send_sensor_config(
+        &mut self,
+        sensor: protocol::SensorIndex,
+        config: protocol::cfg::CFG,
+    ) -> AdisDeviceResult<()> {
+        self.confirmed_send(
+            &protocol::Message::CFG(sensor, config),
+            Some(RESPONSE_TIMEOUT),
+        )?;
+
+        let kind = std::mem::discriminant(&config);
+        match self
+            .config
+            .iter_mut()
+            .find(|(s, c)| *s == sensor && std::mem::discriminant(c) == kind)
+        {
+            Some((_, c)) => *c = config,
+            None => self.config.push((sensor, config)),
+        }
+        return Ok(());
     }
 
     pub fn send_error(&mut self, tag: u8) -> AdisDeviceResult<()> {
-        return self.confirmed_send(&protocol::Message::ERR(tag), Some(Duration::from_millis(1)));
+        return self.confirmed_send(&protocol::Message::ERR(tag), Some(RESPONSE_TIMEOUT));
+    }
+
+    /// Saves, loads or erases configuration stored in the board, the stored one is applied at power up.
+    pub fn send_store(&mut self, store: protocol::Store) -> AdisDeviceResult<()> {
+        return self.confirmed_send(&protocol::Message::STO(store), Some(STORE_TIMEOUT));
     }
 
-    pub fn expect_burst(
+    /// Sets SPI clock and stall time of the sensor, returns the timing applied with the frequency the board achieved.
+    ///
+    /// Timing out of the `SpiTiming` bounds fails with `InvalidSpiTiming` without being sent. Clock faster than
+    /// `MAX_BURST_FREQUENCY_HZ` while bursts are enabled is rejected by the board, it answers with the timing
+    /// still in use.
+    pub fn send_spi_timing(
         &mut self,
-    ) -> AdisDeviceResult<heapless::Vec<protocol::adis::BurstData, 8>> {
-        let mut out = heapless::Vec::new();
+        sensor: protocol::SensorIndex,
+        timing: protocol::SpiTiming,
+    ) -> AdisDeviceResult<protocol::SpiTiming> {
+        if !timing.is_valid() {
+            return Err(AdisDeviceError::InvalidSpiTiming);
+        }
+
+        self.send(&protocol::Message::SpiTiming(sensor, timing))?;
+        let start_time = SystemTime::now();
+
+        while start_time.elapsed()? < RESPONSE_TIMEOUT {
+            let received_messages = self.receive()?;
+            for m in received_messages {
+                if let protocol::Message::SpiTiming(s, applied) = m {
+                    if s == sensor {
+                        match self.spi_timing.iter_mut().find(|(s, _)| *s == sensor) {
+                            Some((_, t)) => *t = applied,
+                            None => self.spi_timing.push((sensor, applied)),
+                        }
+                        return Ok(applied);
+                    }
+                }
+            }
+        }
+
+        return Err(AdisDeviceError::NoResponse);
+    }
+
+    /// Reboots the board into the USB bootloader, the device disappears and the board shows up as a drive.
+    pub fn send_enter_bootloader(&mut self) -> AdisDeviceResult<()> {
+        return self.confirmed_send(&protocol::Message::EnterBootloader, Some(RESPONSE_TIMEOUT));
+    }
+
+    /// Bursts of all sensors on the board.
+    pub fn expect_burst(&mut self) -> AdisDeviceResult<Vec<SensorBurst>> {
         let received_messages = self.receive()?;
 
-        received_messages.iter().for_each(|m| match m {
-            protocol::Message::B16(sel, burst) => {
-                let new_out = match sel {
-                    protocol::cfg::BurstSel::Sel0 => protocol::adis::BurstData::as_sel0(burst, &self.version),
-                    protocol::cfg::BurstSel::Sel1 => protocol::adis::BurstData::as_sel1(burst, &self.version),
-                };
-                out.push(new_out).ok();
+        return Ok(received_messages
+            .iter()
+            .flat_map(|m| burst_data(m, &self.version))
+            .collect());
+    }
+}
+
+/// Board found among serial ports of the system.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeviceInfo {
+    pub port_name: String,
+    pub serial_number: Option<String>,
+    pub vid: u16,
+    pub pid: u16,
+}
+
+/// All connected boards with the default VID and PID.
+pub fn list_devices() -> AdisDeviceResult<Vec<DeviceInfo>> {
+    return usb_ports(protocol::VID_PID.0, protocol::VID_PID.1);
+}
+
+fn usb_ports(vid: u16, pid: u16) -> AdisDeviceResult<Vec<DeviceInfo>> {
+    let devices = serialport::available_ports()?
+        .into_iter()
+        .filter_map(|p| match p.port_type {
+            serialport::SerialPortType::UsbPort(info) if (info.vid, info.pid) == (vid, pid) => {
+                Some(DeviceInfo {
+                    port_name: p.port_name,
+                    serial_number: info.serial_number,
+                    vid,
+                    pid,
+                })
             }
+            _ => None,
+        })
+        .collect();
+
+    return Ok(devices);
+}
 
-            protocol::Message::B32(sel, burst) => {
-                let new_out = match sel {
-                    protocol::cfg::BurstSel::Sel0 => protocol::adis::BurstData::as_sel0(burst, &self.version),
-                    protocol::cfg::BurstSel::Sel1 => protocol::adis::BurstData::as_sel1(burst, &self.version),
-                };
-                out.push(new_out).ok();
+/// Finds USB serial port by VID and PID, and by serial number when given.
+fn find_port(vid: u16, pid: u16, serial_number: Option<&str>) -> AdisDeviceResult<String> {
+    return usb_ports(vid, pid)?
+        .into_iter()
+        .find(|d| serial_number.is_none() || d.serial_number.as_deref() == serial_number)
+        .map(|d| d.port_name)
+        .ok_or(AdisDeviceError::NoPort);
+}
+
+/// Feeds received bytes into the COBS accumulator, every decoded message is passed to `sink`.
+fn decode<F: FnMut(protocol::Message)>(
+    buffer: &mut protocol::CobsAccumulator<MAX_MESSAGE_LEN>,
+    errors: &mut DecodeErrors,
+    mut window: &[u8],
+    mut sink: F,
+) {
+    'cobs: while !window.is_empty() {
+        window = match buffer.feed::<protocol::Message>(window) {
+            protocol::FeedResult::Consumed => break 'cobs,
+            protocol::FeedResult::OverFull(new_wind) => {
+                errors.overfull += 1;
+                new_wind
+            }
+            protocol::FeedResult::DeserError(new_wind) => {
+                errors.deserialization += 1;
+                new_wind
             }
-            _ => (),
+            protocol::FeedResult::Success { data, remaining } => {
+                sink(data);
+                remaining
+            }
+        };
+    }
+}
+
+/// Converts burst or batch message of any sensor into measured data of its bursts, other messages give none.
+pub fn burst_data(
+    message: &protocol::Message,
+    version: &AdisVersion,
+) -> protocol::Vec<SensorBurst, { protocol::MAX_BATCH }> {
+    if let protocol::Message::BAT(batch) = message {
+        let mut bursts: protocol::Vec<_, { protocol::MAX_BATCH }> = batch
+            .messages()
+            .flat_map(|m| burst_data(&m, version))
+            .collect();
+        if let Some(first) = bursts.first_mut() {
+            first.timestamp_us = Some(batch.timestamp_us);
+        }
+        return bursts;
+    }
+
+    let (sensor, data) = match message {
+        protocol::Message::B16(sensor, sel, burst) => (
+            *sensor,
+            match sel {
+                protocol::cfg::BurstSel::Sel0 => protocol::adis::BurstData::as_sel0(burst, version),
+                protocol::cfg::BurstSel::Sel1 => protocol::adis::BurstData::as_sel1(burst, version),
+            },
+        ),
+
+        protocol::Message::B32(sensor, sel, burst) => (
+            *sensor,
+            match sel {
+                protocol::cfg::BurstSel::Sel0 => protocol::adis::BurstData::as_sel0(burst, version),
+                protocol::cfg::BurstSel::Sel1 => protocol::adis::BurstData::as_sel1(burst, version),
+            },
+        ),
+
+        _ => return protocol::Vec::new(),
+    };
+
+    return protocol::Vec::from_iter([SensorBurst {
+        sensor,
+        timestamp_us: None,
+        data,
+    }]);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const VERSION: AdisVersion = AdisVersion::ADIS16505_1BMLZ;
+
+    fn frame(message: &protocol::Message) -> heapless::Vec<u8, MAX_MESSAGE_LEN> {
+        return protocol::to_vec_cobs(message).unwrap();
+    }
+
+    #[test]
+    fn cobs_reassembly() {
+        let (port, mut device) = MemoryTransport::pair();
+        let mut adis = AdisDevice::new(port, VERSION);
+
+        let burst = protocol::Message::B16(0, protocol::cfg::BurstSel::Sel0, Default::default());
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&frame(&burst));
+        bytes.extend_from_slice(&frame(&protocol::Message::RQR(42)));
+        let (first, second) = bytes.split_at(7);
+
+        device.write(first).unwrap();
+        assert!(adis.receive().unwrap().is_empty());
+
+        device.write(second).unwrap();
+        assert_eq!(
+            adis.receive().unwrap().as_slice(),
+            &[burst, protocol::Message::RQR(42)]
+        );
+    }
+
+    #[test]
+    fn batches_carry_board_time() {
+        let (port, mut device) = MemoryTransport::pair();
+        let mut adis = AdisDevice::new(port, VERSION);
+
+        let burst = |cntr| {
+            let mut words = [0; 16];
+            words[14] = cntr;
+            return protocol::Message::B32(1, protocol::cfg::BurstSel::Sel1, words.into());
+        };
+        let mut batch = protocol::Batch::new(burst(0), 1234).unwrap();
+        assert!(batch.push(burst(1)));
+        assert!(batch.push(burst(2)));
+        device
+            .write(&frame(&protocol::Message::BAT(batch)))
+            .unwrap();
+
+        let bursts = adis.expect_burst().unwrap();
+        assert!(bursts.iter().all(|b| b.sensor == 1));
+        assert_eq!(
+            bursts
+                .iter()
+                .map(|b| (b.timestamp_us, b.data.data_cntr))
+                .collect::<Vec<_>>(),
+            [(Some(1234), 0), (None, 1), (None, 2)]
+        );
+    }
+
+    #[test]
+    fn confirmed_send_errors() {
+        let (port, mut device) = MemoryTransport::pair();
+        let mut adis = AdisDevice::new(port, VERSION);
+
+        let timeout = Some(Duration::from_millis(5));
+        assert!(matches!(
+            adis.send_request_response(0x7200, timeout),
+            Err(AdisDeviceError::NoResponse)
+        ));
+
+        device.write(&frame(&protocol::Message::ERR(3))).unwrap();
+        assert!(matches!(
+            adis.send_restart(),
+            Err(AdisDeviceError::DeviceError(3))
+        ));
+
+        let mut loopback = AdisDevice::new(MemoryTransport::loopback(), VERSION);
+        loopback.send_restart().unwrap();
+    }
+
+    #[test]
+    fn spi_timing_is_checked() {
+        let (port, mut device) = MemoryTransport::pair();
+        let mut adis = AdisDevice::new(port, VERSION);
+
+        // timing out of bounds does not reach the board
+        let slow = protocol::SpiTiming {
+            frequency_hz: 10_000,
+            ..Default::default()
+        };
+        assert!(matches!(
+            adis.send_spi_timing(0, slow),
+            Err(AdisDeviceError::InvalidSpiTiming)
+        ));
+        let mut buf = [0; 16];
+        assert_eq!(device.read_available(&mut buf).unwrap(), 0);
+
+        // rejected by the board, the timing in use comes back and is the one kept
+        let current = protocol::SpiTiming::default();
+        device
+            .write(&frame(&protocol::Message::SpiTiming(0, current)))
+            .unwrap();
+        let fast = protocol::SpiTiming {
+            frequency_hz: protocol::SpiTiming::MAX_FREQUENCY_HZ,
+            ..Default::default()
+        };
+        assert_eq!(adis.send_spi_timing(0, fast).unwrap(), current);
+        assert_eq!(adis.applied_spi_timing(), [(0, current)]);
+    }
+
+    #[test]
+    fn status_and_heartbeat() {
+        let (port, mut device) = MemoryTransport::pair();
+        let mut adis = AdisDevice::new(port, VERSION);
+
+        let status = protocol::Status {
+            uptime_ms: 1000,
+            bursts: 7,
+            ..Default::default()
+        };
+        device
+            .write(&frame(&protocol::Message::Status(status)))
+            .unwrap();
+        assert_eq!(
+            adis.send_status_request(Some(Duration::from_millis(100)))
+                .unwrap(),
+            status
+        );
+        assert_eq!(adis.last_status(), Some(&status));
+
+        let mut buf = [0; 16];
+        let len = device.read_available(&mut buf).unwrap();
+        assert_eq!(buf[..len], frame(&protocol::Message::StatusRequest));
+
+        // bursts are no heartbeat, but they are not lost with the timeout
+        adis.set_heartbeat_timeout(Some(Duration::from_millis(20)));
+        assert!(adis.receive().unwrap().is_empty());
+        std::thread::sleep(Duration::from_millis(25));
+        let burst = protocol::Message::B32(0, protocol::cfg::BurstSel::Sel0, Default::default());
+        device.write(&frame(&burst)).unwrap();
+        assert!(matches!(
+            adis.receive(),
+            Err(AdisDeviceError::HeartbeatTimeout)
+        ));
+
+        device
+            .write(&frame(&protocol::Message::Status(status)))
+            .unwrap();
+        assert_eq!(
+            adis.receive().unwrap(),
+            [burst, protocol::Message::Status(status)]
+        );
+    }
+
+    #[test]
+    fn handlers() {
+        use std::sync::{Arc, Mutex};
+
+        let (port, mut device) = MemoryTransport::pair();
+        let mut adis = AdisDevice::new(port, VERSION);
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let e = Arc::clone(&events);
+        adis.on_burst(move |b| {
+            e.lock()
+                .unwrap()
+                .push(format!("burst {} {}", b.sensor, b.data.data_cntr))
+        });
+        let e = Arc::clone(&events);
+        adis.on_diag_fault(move |b| {
+            e.lock()
+                .unwrap()
+                .push(format!("fault {}", b.data.data_cntr))
+        });
+        let e = Arc::clone(&events);
+        adis.on_sample_gap(move |g| {
+            e.lock()
+                .unwrap()
+                .push(format!("gap {} {}", g.sensor, g.missing))
+        });
+        let e = Arc::clone(&events);
+        adis.on_overflow(move |source, dropped| {
+            e.lock()
+                .unwrap()
+                .push(format!("overflow {:?} {}", source, dropped))
+        });
+        let e = Arc::clone(&events);
+        adis.on_health(move |sensor, health| {
+            e.lock()
+                .unwrap()
+                .push(format!("health {} {:?}", sensor, health))
         });
 
-        return Ok(out);
+        // sensors count on their own, interleaving them is no gap
+        for (sensor, cntr, diag_stat) in
+            [(0, 1, 0), (1, 7, 0), (0, 2, 0), (1, 8, 0), (0, 5, 1 << 3)]
+        {
+            if cntr == 5 {
+                device
+                    .write(&frame(&protocol::Message::Overflow {
+                        source: protocol::OverflowSource::Usb,
+                        dropped: 2,
+                    }))
+                    .unwrap();
+                device
+                    .write(&frame(&protocol::Message::Health(
+                        0,
+                        protocol::Health::Recovered,
+                    )))
+                    .unwrap();
+            }
+            let mut words = [0; 10];
+            words[0] = diag_stat;
+            words[8] = cntr;
+            // burst checksum covers the bytes of all words but the last one
+            words[9] = words[..9].iter().map(|w| (w >> 8) + (w & 0xff)).sum();
+            device
+                .write(&frame(&protocol::Message::B16(
+                    sensor,
+                    protocol::cfg::BurstSel::Sel0,
+                    words.into(),
+                )))
+                .unwrap();
+        }
+
+        let mut polls = 0;
+        adis.run(|| {
+            polls += 1;
+            return polls <= 1;
+        })
+        .unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "burst 0 1",
+                "burst 1 7",
+                "burst 0 2",
+                "burst 1 8",
+                "overflow Usb 2",
+                "health 0 Recovered",
+                "burst 0 5",
+                "fault 5",
+                "gap 0 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn nothing_is_dropped() {
+        let (port, mut device) = MemoryTransport::pair();
+        let mut adis = AdisDevice::new(port, VERSION);
+
+        let burst = protocol::Message::B32(0, protocol::cfg::BurstSel::Sel0, Default::default());
+        for _ in 0..100 {
+            device.write(&frame(&burst)).unwrap();
+        }
+        device.write(&frame(&protocol::Message::ERR(1))).unwrap();
+        device.write(&frame(&burst)).unwrap();
+        device.write(&[0x01, 0x7f, 0x00]).unwrap();
+        device.write(&[0x01; MAX_MESSAGE_LEN + 1]).unwrap();
+        device.write(&[0x00]).unwrap();
+
+        assert!(matches!(
+            adis.receive(),
+            Err(AdisDeviceError::DeviceError(1))
+        ));
+        assert_eq!(adis.expect_burst().unwrap().len(), 101);
+        assert_eq!(
+            adis.decode_errors(),
+            DecodeErrors {
+                overfull: 1,
+                deserialization: 1
+            }
+        );
     }
 }
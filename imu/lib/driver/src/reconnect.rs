@@ -0,0 +1,513 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use serialport5 as serialport;
+
+use super::handlers::{Handlers, SampleGap};
+use super::{
+    burst_data, find_port, protocol, AdisDevice, AdisDeviceError, AdisDeviceResult, AdisVersion,
+    Duration, SensorBurst, Transport,
+};
+
+/// What `ResilientDevice` reports while reading.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    Burst(SensorBurst),
+    /// Samples of the sensor are missing before its next burst, `None` if it is not known how many.
+    /// Gap of all sensors has no `sensor`.
+    Gap {
+        sensor: Option<protocol::SensorIndex>,
+        missing: Option<u16>,
+    },
+    /// Device sends error with tag.
+    DeviceError(u8),
+    /// Device dropped messages at `source`, because they were not taken in time.
+    Overflow {
+        source: protocol::OverflowSource,
+        dropped: u32,
+    },
+    /// Device reports health of the sensor, it resets those that stop working.
+    Health {
+        sensor: protocol::SensorIndex,
+        health: protocol::Health,
+    },
+    /// No `Status` came from the device for the heartbeat timeout.
+    HeartbeatTimeout,
+    Disconnected,
+    /// Device is back and configured as before, `downtime` since the disconnect was noticed.
+    Reconnected {
+        downtime: Duration,
+    },
+}
+
+type Opener<T> = Box<dyn FnMut() -> AdisDeviceResult<T> + Send>;
+
+/// Device that is opened again whenever it disappears.
+///
+/// After reconnection the device is restarted and the configuration it had is applied again,
+/// SPI timing first and bursts last, so the stream continues with a `Gap` and `Reconnected` events.
+pub struct ResilientDevice<T = serialport::SerialPort> {
+    open: Opener<T>,
+    version: AdisVersion,
+    device: Option<AdisDevice<T>>,
+    config: Vec<(protocol::SensorIndex, protocol::cfg::CFG)>,
+    spi_timing: Vec<(protocol::SensorIndex, protocol::SpiTiming)>,
+    /// Restart the device whenever it is opened.
+    reset: bool,
+    heartbeat_timeout: Option<Duration>,
+    retry_period: Duration,
+    last_attempt: Instant,
+    disconnected_at: Instant,
+    /// Last counter of every sensor.
+    last_cntr: HashMap<protocol::SensorIndex, u16>,
+    events: VecDeque<Event>,
+    handlers: Handlers,
+}
+
+impl ResilientDevice {
+    /// Finds the device by VID and PID (and serial number, to tell apart more devices) on every reconnection.
+    pub fn from_vid_pid(
+        vid: u16,
+        pid: u16,
+        serial_number: Option<String>,
+        baud_rate: u32,
+        version: AdisVersion,
+        timeout: Option<Duration>,
+    ) -> AdisDeviceResult<Self> {
+        let open = move || {
+            let path = find_port(vid, pid, serial_number.as_deref())?;
+            let port = serialport::SerialPort::builder()
+                .baud_rate(baud_rate)
+                .read_timeout(timeout)
+                .open(path)?;
+            return Ok(port);
+        };
+
+        return Self::new(open, version);
+    }
+}
+
+impl<T: Transport> ResilientDevice<T> {
+    /// Opens and restarts the device, `open` is called again after every disconnect.
+    pub fn new<F>(open: F, version: AdisVersion) -> AdisDeviceResult<Self>
+    where
+        F: FnMut() -> AdisDeviceResult<T> + Send + 'static,
+    {
+        return Self::open(open, version, true);
+    }
+
+    /// Opens the device as it is, a stream the board runs on its own goes on.
+    ///
+    /// The device is not restarted after reconnection either, only configuration sent through it is applied again,
+    /// the SPI timing is left as it is.
+    pub fn attach<F>(open: F, version: AdisVersion) -> AdisDeviceResult<Self>
+    where
+        F: FnMut() -> AdisDeviceResult<T> + Send + 'static,
+    {
+        return Self::open(open, version, false);
+    }
+
+    fn open<F>(open: F, version: AdisVersion, reset: bool) -> AdisDeviceResult<Self>
+    where
+        F: FnMut() -> AdisDeviceResult<T> + Send + 'static,
+    {
+        let mut device = Self {
+            open: Box::new(open),
+            version,
+            device: None,
+            config: Vec::new(),
+            spi_timing: Vec::new(),
+            reset,
+            heartbeat_timeout: None,
+            retry_period: Duration::from_millis(500),
+            last_attempt: Instant::now(),
+            disconnected_at: Instant::now(),
+            last_cntr: HashMap::new(),
+            events: VecDeque::new(),
+            handlers: Handlers::default(),
+        };
+
+        device.device = Some(device.connect()?);
+        return Ok(device);
+    }
+
+    pub fn with_retry_period(mut self, retry_period: Duration) -> Self {
+        self.retry_period = retry_period;
+        return self;
+    }
+
+    /// Reports `HeartbeatTimeout` whenever the device sends no `Status` for `timeout`, kept across reconnections.
+    pub fn with_heartbeat_timeout(mut self, timeout: Duration) -> Self {
+        self.heartbeat_timeout = Some(timeout);
+        if let Some(device) = self.device.as_mut() {
+            device.set_heartbeat_timeout(self.heartbeat_timeout);
+        }
+        return self;
+    }
+
+    /// The device while it is connected, configuration sent through it is restored after reconnection.
+    pub fn device(&mut self) -> Option<&mut AdisDevice<T>> {
+        return self.device.as_mut();
+    }
+
+    pub fn is_connected(&self) -> bool {
+        return self.device.is_some();
+    }
+
+    /// Waits at most about `timeout` for next event, `None` if there was none.
+    pub fn next_event(&mut self, timeout: Duration) -> Option<Event> {
+        if let Some(event) = self.events.pop_front() {
+            return Some(event);
+        }
+
+        match self.device.as_mut() {
+            Some(device) => match device.receive_timeout(timeout) {
+                Ok(messages) => {
+                    let version = self.version;
+                    for message in messages {
+                        match message {
+                            protocol::Message::Overflow { source, dropped } => {
+                                self.events.push_back(Event::Overflow { source, dropped });
+                            }
+                            protocol::Message::Health(sensor, health) => {
+                                self.events.push_back(Event::Health { sensor, health });
+                            }
+                            _ => {
+                                for burst in burst_data(&message, &version) {
+                                    push_burst(
+                                        &mut self.events,
+                                        &mut self.last_cntr,
+                                        &mut device.handlers,
+                                        burst,
+                                    );
+                                }
+                            }
+                        }
+                    }
+                }
+                Err(AdisDeviceError::DeviceError(tag)) => {
+                    self.events.push_back(Event::DeviceError(tag));
+                }
+                Err(AdisDeviceError::HeartbeatTimeout) => {
+                    self.events.push_back(Event::HeartbeatTimeout);
+                }
+                Err(e) if e.is_disconnect() => {
+                    // handlers and configuration move over to the device opened next
+                    self.config = device.applied_config().to_vec();
+                    self.spi_timing = device.applied_spi_timing().to_vec();
+                    self.handlers = std::mem::take(&mut device.handlers);
+                    self.device = None;
+                    self.disconnected_at = Instant::now();
+                    self.events.push_back(Event::Disconnected);
+                }
+                Err(_) => {}
+            },
+            None => self.reconnect(timeout),
+        }
+
+        return self.events.pop_front();
+    }
+
+    fn reconnect(&mut self, timeout: Duration) {
+        let next_attempt = self.last_attempt + self.retry_period;
+        let now = Instant::now();
+        if now < next_attempt {
+            std::thread::sleep(std::cmp::min(timeout, next_attempt - now));
+            return;
+        }
+
+        if let Ok(device) = self.connect() {
+            self.device = Some(device);
+            self.events.push_back(Event::Gap {
+                sensor: None,
+                missing: None,
+            });
+            self.events.push_back(Event::Reconnected {
+                downtime: self.disconnected_at.elapsed(),
+            });
+        }
+    }
+
+    fn connect(&mut self) -> AdisDeviceResult<AdisDevice<T>> {
+        self.last_attempt = Instant::now();
+
+        let mut device = AdisDevice::new((self.open)()?, self.version);
+        device.handlers = std::mem::take(&mut self.handlers);
+        device.handlers.counted_by_owner = true;
+        // counters of the previous connection mean nothing, even when the device is not restarted
+        self.last_cntr.clear();
+        device.set_heartbeat_timeout(self.heartbeat_timeout);
+
+        if let Err(e) = configure(&mut device, &self.spi_timing, &self.config, self.reset) {
+            self.handlers = std::mem::take(&mut device.handlers);
+            return Err(e);
+        }
+
+        return Ok(device);
+    }
+}
+
+/// Counts bursts handed out, those read while waiting for acknowledgement are missing as well.
+fn push_burst(
+    events: &mut VecDeque<Event>,
+    last_cntr: &mut HashMap<protocol::SensorIndex, u16>,
+    handlers: &mut Handlers,
+    burst: SensorBurst,
+) {
+    if !burst.data.corrupted {
+        let current = burst.data.data_cntr;
+        if let Some(previous) = last_cntr.insert(burst.sensor, current) {
+            let missing = current.wrapping_sub(previous).wrapping_sub(1);
+            if missing != 0 {
+                handlers.sample_gap(SampleGap {
+                    sensor: burst.sensor,
+                    previous,
+                    current,
+                    missing,
+                });
+                events.push_back(Event::Gap {
+                    sensor: Some(burst.sensor),
+                    missing: Some(missing),
+                });
+            }
+        }
+    }
+
+    events.push_back(Event::Burst(burst));
+}
+
+/// Restarts the device if asked to and applies `spi_timing` and `config`, bursts are enabled last.
+///
+/// Device not restarted keeps its SPI timing, unless it lost power, and may stream already, when faster clock
+/// would be rejected, so the timing is applied only after restart.
+fn configure<T: Transport>(
+    device: &mut AdisDevice<T>,
+    spi_timing: &[(protocol::SensorIndex, protocol::SpiTiming)],
+    config: &[(protocol::SensorIndex, protocol::cfg::CFG)],
+    reset: bool,
+) -> AdisDeviceResult<()> {
+    if reset {
+        device.send_restart()?;
+    }
+
+    // faster clock is rejected while bursts are enabled
+    if reset {
+        for (sensor, timing) in spi_timing {
+            device.send_spi_timing(*sensor, *timing)?;
+        }
+    }
+
+    let (burst_en, config): (Vec<_>, Vec<_>) = config
+        .iter()
+        .copied()
+        .partition(|(_, c)| matches!(c, protocol::cfg::CFG::BurstEn(_)));
+    for (sensor, cfg) in config.into_iter().chain(burst_en) {
+        device.send_sensor_config(sensor, cfg)?;
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::MemoryTransport;
+    use std::sync::{Arc, Mutex};
+
+    const VERSION: AdisVersion = AdisVersion::ADIS16505_1BMLZ;
+
+    fn write_burst(port: &mut MemoryTransport, cntr: u16) {
+        write_sensor_burst(port, 0, cntr);
+    }
+
+    fn write_sensor_burst(port: &mut MemoryTransport, sensor: protocol::SensorIndex, cntr: u16) {
+        let mut words = [0; 10];
+        words[8] = cntr;
+        words[9] = (cntr >> 8) + (cntr & 0xff);
+        let burst = protocol::Message::B16(sensor, protocol::cfg::BurstSel::Sel0, words.into());
+        let frame: heapless::Vec<u8, 64> = protocol::to_vec_cobs(&burst).unwrap();
+        port.write(&frame).unwrap();
+    }
+
+    fn events(device: &mut ResilientDevice<MemoryTransport>) -> Vec<Event> {
+        let mut events = Vec::new();
+        while let Some(event) = device.next_event(Duration::from_millis(10)) {
+            events.push(event);
+        }
+        return events;
+    }
+
+    #[test]
+    fn reconnects_and_restores_config() {
+        // loopback echoes every command back as confirmation
+        let ports = Arc::new(Mutex::new(vec![
+            MemoryTransport::loopback(),
+            MemoryTransport::loopback(),
+        ]));
+        let (mut first, mut second) = {
+            let ports = ports.lock().unwrap();
+            (ports[0].clone(), ports[1].clone())
+        };
+        let opener_ports = Arc::clone(&ports);
+        let open = move || {
+            let mut ports = opener_ports.lock().unwrap();
+            return if ports.is_empty() {
+                Err(AdisDeviceError::NoPort)
+            } else {
+                Ok(ports.remove(0))
+            };
+        };
+
+        let mut adis = ResilientDevice::new(open, VERSION)
+            .unwrap()
+            .with_retry_period(Duration::ZERO);
+        let device = adis.device().unwrap();
+        let config = [
+            protocol::cfg::CFG::BurstEn(true),
+            protocol::cfg::CFG::BurstSel(protocol::cfg::BurstSel::Sel0),
+        ];
+        config.iter().for_each(|c| device.send_config(*c).unwrap());
+        let timing = protocol::SpiTiming {
+            frequency_hz: 2_000_000,
+            stall_us: 20,
+        };
+        device.send_spi_timing(1, timing).unwrap();
+
+        let bursts = Arc::new(Mutex::new(0));
+        let handler_bursts = Arc::clone(&bursts);
+        device.on_burst(move |_| *handler_bursts.lock().unwrap() += 1);
+
+        write_burst(&mut first, 1);
+        write_sensor_burst(&mut first, 1, 9);
+        write_burst(&mut first, 3);
+        assert!(matches!(
+            events(&mut adis)[..],
+            [
+                Event::Burst(_),
+                Event::Burst(SensorBurst { sensor: 1, .. }),
+                Event::Gap {
+                    sensor: Some(0),
+                    missing: Some(1)
+                },
+                Event::Burst(_)
+            ]
+        ));
+
+        first.disconnect();
+        assert_eq!(
+            adis.next_event(Duration::from_millis(10)),
+            Some(Event::Disconnected)
+        );
+        assert!(!adis.is_connected());
+        assert!(matches!(
+            events(&mut adis)[..],
+            [
+                Event::Gap {
+                    sensor: None,
+                    missing: None
+                },
+                Event::Reconnected { .. }
+            ]
+        ));
+
+        // the device restarted, so counter starting over is no gap
+        write_burst(&mut second, 1);
+        assert!(matches!(events(&mut adis)[..], [Event::Burst(_)]));
+
+        let device = adis.device().unwrap();
+        assert_eq!(device.applied_config(), [(0, config[1]), (0, config[0])]);
+        assert_eq!(device.applied_spi_timing(), [(1, timing)]);
+        assert_eq!(*bursts.lock().unwrap(), 4);
+    }
+
+    #[test]
+    fn attaches_without_reset() {
+        let (port, mut board) = MemoryTransport::pair();
+        let mut port = Some(port);
+        let mut adis =
+            ResilientDevice::attach(move || port.take().ok_or(AdisDeviceError::NoPort), VERSION)
+                .unwrap();
+
+        // the board streams on its own and hears nothing from the host
+        write_burst(&mut board, 7);
+        assert!(matches!(events(&mut adis)[..], [Event::Burst(_)]));
+
+        let mut buf = [0; 16];
+        assert_eq!(board.read_available(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn attached_reconnect_is_no_gap() {
+        let (first, mut first_board) = MemoryTransport::pair();
+        let (second, mut second_board) = MemoryTransport::pair();
+        let mut ports = vec![first, second];
+        let open = move || {
+            return if ports.is_empty() {
+                Err(AdisDeviceError::NoPort)
+            } else {
+                Ok(ports.remove(0))
+            };
+        };
+
+        let mut adis = ResilientDevice::attach(open, VERSION)
+            .unwrap()
+            .with_retry_period(Duration::ZERO);
+        let device = adis.device().unwrap();
+        let gaps = Arc::new(Mutex::new(Vec::new()));
+        let handler_gaps = Arc::clone(&gaps);
+        device.on_sample_gap(move |g| handler_gaps.lock().unwrap().push(g.missing));
+
+        // the board answers the timing ahead, it streams at full speed already
+        let timing = protocol::SpiTiming {
+            frequency_hz: 2_000_000,
+            stall_us: 20,
+        };
+        let frame: heapless::Vec<u8, 64> =
+            protocol::to_vec_cobs(&protocol::Message::SpiTiming(0, timing)).unwrap();
+        first_board.write(&frame).unwrap();
+        device.send_spi_timing(0, timing).unwrap();
+
+        write_burst(&mut first_board, 1);
+        write_burst(&mut first_board, 3);
+        assert!(matches!(
+            events(&mut adis)[..],
+            [
+                Event::Burst(_),
+                Event::Gap {
+                    sensor: Some(0),
+                    missing: Some(1)
+                },
+                Event::Burst(_)
+            ]
+        ));
+
+        first_board.disconnect();
+        assert_eq!(
+            adis.next_event(Duration::from_millis(10)),
+            Some(Event::Disconnected)
+        );
+        assert!(matches!(
+            events(&mut adis)[..],
+            [
+                Event::Gap {
+                    sensor: None,
+                    missing: None
+                },
+                Event::Reconnected { .. }
+            ]
+        ));
+
+        // the stream went on meanwhile, the counter of the new connection starts the count
+        write_burst(&mut second_board, 50);
+        write_burst(&mut second_board, 51);
+        assert!(matches!(
+            events(&mut adis)[..],
+            [Event::Burst(_), Event::Burst(_)]
+        ));
+        assert_eq!(*gaps.lock().unwrap(), [1]);
+
+        // timing of the streaming board is left as it is
+        let mut buf = [0; 16];
+        assert_eq!(second_board.read_available(&mut buf).unwrap(), 0);
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+
+use super::{
+    burst_data, AdisDevice, AdisDeviceResult, DecodeErrors, Duration, SensorBurst, Transport,
+};
+
+/// How long the reader waits for data before checking whether it should stop.
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// What the reader does when the consumer does not keep up and the channel is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum OverflowPolicy {
+    /// Wait for the consumer, data pile up in the OS buffer (and get lost there once it is full).
+    Block,
+    /// Discard the oldest burst in the channel.
+    DropOldest,
+    /// Discard the burst just received.
+    DropNewest,
+}
+
+type Item = AdisDeviceResult<SensorBurst>;
+
+/// Bursts of all sensors read by a background thread, delivered through a bounded channel.
+///
+/// The device is owned by the reader thread until the stream is stopped.
+pub struct AdisStream<T> {
+    shared: Arc<Shared>,
+    reader: Option<JoinHandle<AdisDevice<T>>>,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    pushed: Condvar,
+    popped: Condvar,
+    stop: AtomicBool,
+    received: AtomicU64,
+    dropped: AtomicU64,
+    decode_errors: Mutex<DecodeErrors>,
+}
+
+struct Queue {
+    items: VecDeque<Item>,
+    closed: bool,
+}
+
+impl<T: Transport + Send + 'static> AdisStream<T> {
+    pub fn new(device: AdisDevice<T>, capacity: usize, policy: OverflowPolicy) -> Self {
+        let capacity = capacity.max(1);
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                items: VecDeque::with_capacity(capacity),
+                closed: false,
+            }),
+            pushed: Condvar::new(),
+            popped: Condvar::new(),
+            stop: AtomicBool::new(false),
+            received: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+            decode_errors: Mutex::new(DecodeErrors::default()),
+        });
+
+        let reader_shared = Arc::clone(&shared);
+        let reader = thread::spawn(move || read_loop(device, &reader_shared, capacity, policy));
+
+        return Self {
+            shared,
+            reader: Some(reader),
+        };
+    }
+
+    /// Stops the reader thread and gives the device back.
+    pub fn stop(mut self) -> AdisDevice<T> {
+        self.shared.request_stop();
+
+        let reader = self.reader.take().expect("Reader is joined only once.");
+        return match reader.join() {
+            Ok(device) => device,
+            Err(e) => std::panic::resume_unwind(e),
+        };
+    }
+}
+
+impl<T> AdisStream<T> {
+    /// Number of bursts received from the device.
+    pub fn received(&self) -> u64 {
+        return self.shared.received.load(Ordering::Relaxed);
+    }
+
+    /// Number of bursts discarded because of full channel.
+    pub fn dropped(&self) -> u64 {
+        return self.shared.dropped.load(Ordering::Relaxed);
+    }
+
+    /// Frames the reader could not decode.
+    pub fn decode_errors(&self) -> DecodeErrors {
+        return *self
+            .shared
+            .decode_errors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner());
+    }
+
+    /// Waits at most `timeout` for next burst, `None` on timeout or when the reader has ended.
+    pub fn recv_timeout(&mut self, timeout: Duration) -> Option<Item> {
+        return self.shared.pop(Some(timeout));
+    }
+}
+
+impl<T> Iterator for AdisStream<T> {
+    type Item = Item;
+
+    /// Blocks until next burst, ends when the reader ends on IO error.
+    fn next(&mut self) -> Option<Item> {
+        return self.shared.pop(None);
+    }
+}
+
+impl<T> Drop for AdisStream<T> {
+    fn drop(&mut self) {
+        self.shared.request_stop();
+        if let Some(reader) = self.reader.take() {
+            reader.join().ok();
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, Queue> {
+        return self.queue.lock().unwrap_or_else(|e| e.into_inner());
+    }
+
+    fn request_stop(&self) {
+        self.stop.store(true, Ordering::Relaxed);
+        let _queue = self.lock();
+        self.popped.notify_all();
+    }
+
+    fn push(&self, item: Item, capacity: usize, policy: OverflowPolicy) {
+        let mut queue = self.lock();
+
+        // errors are always delivered
+        if item.is_ok() && queue.items.len() >= capacity {
+            match policy {
+                OverflowPolicy::Block => {
+                    queue = self
+                        .popped
+                        .wait_while(queue, |q| {
+                            q.items.len() >= capacity && !self.stop.load(Ordering::Relaxed)
+                        })
+                        .unwrap_or_else(|e| e.into_inner());
+                    if queue.items.len() >= capacity {
+                        return;
+                    }
+                }
+                OverflowPolicy::DropOldest => {
+                    queue.items.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                OverflowPolicy::DropNewest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+            }
+        }
+
+        queue.items.push_back(item);
+        self.pushed.notify_one();
+    }
+
+    fn pop(&self, timeout: Option<Duration>) -> Option<Item> {
+        let queue = self.lock();
+        let empty = |q: &mut Queue| q.items.is_empty() && !q.closed;
+
+        let mut queue = match timeout {
+            Some(t) => {
+                self.pushed
+                    .wait_timeout_while(queue, t, empty)
+                    .unwrap_or_else(|e| e.into_inner())
+                    .0
+            }
+            None => self
+                .pushed
+                .wait_while(queue, empty)
+                .unwrap_or_else(|e| e.into_inner()),
+        };
+
+        let item = queue.items.pop_front();
+        if item.is_some() {
+            self.popped.notify_one();
+        }
+        return item;
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.pushed.notify_all();
+    }
+}
+
+fn read_loop<T: Transport>(
+    mut device: AdisDevice<T>,
+    shared: &Shared,
+    capacity: usize,
+    policy: OverflowPolicy,
+) -> AdisDevice<T> {
+    while !shared.stop.load(Ordering::Relaxed) {
+        let received = device.receive_timeout(READ_TIMEOUT);
+        *shared
+            .decode_errors
+            .lock()
+            .unwrap_or_else(|e| e.into_inner()) = device.decode_errors();
+
+        match received {
+            Ok(messages) => {
+                for burst in messages.iter().flat_map(|m| burst_data(m, &device.version)) {
+                    shared.received.fetch_add(1, Ordering::Relaxed);
+                    shared.push(Ok(burst), capacity, policy);
+                }
+            }
+            Err(e) => {
+                let fatal = e.is_disconnect();
+                shared.push(Err(e), capacity, policy);
+                if fatal {
+                    break;
+                }
+            }
+        }
+    }
+
+    shared.close();
+    return device;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{protocol, AdisVersion, MemoryTransport};
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    fn write_burst(port: &mut MemoryTransport, cntr: u16) {
+        let mut words = [0; 10];
+        words[8] = cntr;
+        let burst = protocol::Message::B16(0, protocol::cfg::BurstSel::Sel0, words.into());
+        let frame: heapless::Vec<u8, 64> = protocol::to_vec_cobs(&burst).unwrap();
+        port.write(&frame).unwrap();
+    }
+
+    /// Waits at most a second for the reader to receive `received` bursts.
+    fn wait_received<T>(stream: &AdisStream<T>, received: u64) {
+        let start = Instant::now();
+        while stream.received() < received && start.elapsed() < Duration::from_secs(1) {
+            thread::yield_now();
+        }
+    }
+
+    fn stream(policy: OverflowPolicy) -> Vec<u16> {
+        let (port, mut device) = MemoryTransport::pair();
+        let mut stream = AdisStream::new(
+            AdisDevice::new(port, AdisVersion::ADIS16505_1BMLZ),
+            4,
+            policy,
+        );
+
+        for i in 0..10 {
+            write_burst(&mut device, i);
+            wait_received(&stream, i as u64 + 1);
+        }
+
+        let mut counters = Vec::new();
+        while let Some(burst) = stream.recv_timeout(Duration::from_millis(50)) {
+            counters.push(burst.unwrap().data.data_cntr);
+        }
+
+        assert_eq!(stream.received(), 10);
+        assert_eq!(stream.dropped(), 10 - counters.len() as u64);
+        stream.stop();
+        return counters;
+    }
+
+    #[test]
+    fn overflow_policies() {
+        assert_eq!(stream(OverflowPolicy::DropOldest), [6, 7, 8, 9]);
+        assert_eq!(stream(OverflowPolicy::DropNewest), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn blocking_reader_waits_for_consumer() {
+        let (port, mut device) = MemoryTransport::pair();
+        let mut stream = AdisStream::new(
+            AdisDevice::new(port, AdisVersion::ADIS16505_1BMLZ),
+            4,
+            OverflowPolicy::Block,
+        );
+
+        // the consumer stalls, the reader holds the fifth burst until there is room
+        (0..10).for_each(|i| write_burst(&mut device, i));
+        wait_received(&stream, 5);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(stream.received(), 5);
+
+        let counters: Vec<_> = (0..10)
+            .map(|_| {
+                let burst = stream.recv_timeout(Duration::from_secs(1)).unwrap();
+                return burst.unwrap().data.data_cntr;
+            })
+            .collect();
+        assert_eq!(counters, (0..10).collect::<Vec<_>>());
+        assert_eq!(stream.dropped(), 0);
+
+        // dropping the stream wakes the reader waiting on the full channel
+        (10..20).for_each(|i| write_burst(&mut device, i));
+        wait_received(&stream, 15);
+        let (dropped, done) = mpsc::channel();
+        thread::spawn(move || {
+            drop(stream);
+            dropped.send(()).unwrap();
+        });
+        assert!(done.recv_timeout(Duration::from_secs(1)).is_ok());
+    }
+}
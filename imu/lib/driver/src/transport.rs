@@ -0,0 +1,252 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use serialport5 as serialport;
+
+/// Byte stream connecting the driver with the device.
+pub trait Transport {
+    /// Reads whatever is available without waiting, returns 0 if there is nothing to read.
+    fn read_available(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    /// Waits at most `timeout` for data to arrive, returns 0 if nothing came.
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let start = Instant::now();
+        loop {
+            let read_bytes = self.read_available(buf)?;
+            if read_bytes > 0 || start.elapsed() >= timeout {
+                return Ok(read_bytes);
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    /// Writes the whole buffer.
+    fn write(&mut self, buf: &[u8]) -> io::Result<()>;
+
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+impl Transport for serialport::SerialPort {
+    fn read_available(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let bytes_to_read = self.bytes_to_read()?;
+        let bytes_to_read = std::cmp::min(bytes_to_read as usize, buf.len());
+
+        if bytes_to_read == 0 {
+            return Ok(0);
+        }
+
+        return Read::read(self, &mut buf[..bytes_to_read]);
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        // blocking reads through `Read` keep the timeout the port was opened with
+        let previous = serialport::SerialPort::read_timeout(self);
+        self.set_read_timeout(Some(timeout))?;
+
+        let read = match Read::read(self, buf) {
+            Err(e) if e.kind() == io::ErrorKind::TimedOut => Ok(0),
+            r => r,
+        };
+
+        self.set_read_timeout(previous)?;
+        return read;
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        return self.write_all(buf);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Write::flush(self);
+    }
+}
+
+/// In-memory transport, mainly for testing.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryTransport {
+    rx: Arc<Pipe>,
+    tx: Arc<Pipe>,
+}
+
+#[derive(Debug, Default)]
+struct Pipe {
+    data: Mutex<VecDeque<u8>>,
+    written: Condvar,
+    closed: AtomicBool,
+}
+
+impl MemoryTransport {
+    /// Two connected ends, what is written into one is read from the other.
+    pub fn pair() -> (Self, Self) {
+        let a = Arc::new(Pipe::default());
+        let b = Arc::new(Pipe::default());
+
+        return (
+            Self {
+                rx: Arc::clone(&a),
+                tx: Arc::clone(&b),
+            },
+            Self { rx: b, tx: a },
+        );
+    }
+
+    /// Single end reading back whatever was written into it.
+    pub fn loopback() -> Self {
+        let buffer = Arc::new(Pipe::default());
+
+        return Self {
+            rx: Arc::clone(&buffer),
+            tx: buffer,
+        };
+    }
+
+    /// Simulates unplugged device, both ends fail on any further read or write.
+    pub fn disconnect(&self) {
+        for pipe in [&self.rx, &self.tx] {
+            pipe.closed.store(true, Ordering::Relaxed);
+            let _data = pipe.data.lock().unwrap_or_else(|e| e.into_inner());
+            pipe.written.notify_all();
+        }
+    }
+}
+
+impl Pipe {
+    fn check_open(&self) -> io::Result<()> {
+        if self.closed.load(Ordering::Relaxed) {
+            return Err(io::ErrorKind::BrokenPipe.into());
+        }
+        return Ok(());
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn read_available(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return self.read_timeout(buf, Duration::ZERO);
+    }
+
+    fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+        let rx = self.rx.data.lock().unwrap_or_else(|e| e.into_inner());
+        let (mut rx, _) = self
+            .rx
+            .written
+            .wait_timeout_while(rx, timeout, |rx| {
+                rx.is_empty() && !self.rx.closed.load(Ordering::Relaxed)
+            })
+            .unwrap_or_else(|e| e.into_inner());
+        self.rx.check_open()?;
+        let len = std::cmp::min(rx.len(), buf.len());
+
+        for (b, r) in buf.iter_mut().zip(rx.drain(..len)) {
+            *b = r;
+        }
+
+        return Ok(len);
+    }
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.tx.check_open()?;
+        self.tx
+            .data
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .extend(buf);
+        self.tx.written.notify_all();
+
+        return Ok(());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+#[cfg(unix)]
+pub use pty::PtyTransport;
+
+#[cfg(unix)]
+mod pty {
+    use std::fs::{File, OpenOptions};
+    use std::io::{self, Read, Write};
+    use std::mem::MaybeUninit;
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::path::Path;
+    use std::time::Duration;
+
+    /// Pseudo-terminal (or other character device) opened directly, without serial port settings.
+    #[derive(Debug)]
+    pub struct PtyTransport {
+        file: File,
+    }
+
+    impl PtyTransport {
+        pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .custom_flags(libc::O_NOCTTY | libc::O_NONBLOCK)
+                .open(path)?;
+
+            // raw mode, the line discipline must not echo or translate the frames
+            unsafe {
+                let mut termios = MaybeUninit::uninit();
+                if libc::tcgetattr(file.as_raw_fd(), termios.as_mut_ptr()) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                let mut termios = termios.assume_init();
+                libc::cfmakeraw(&mut termios);
+                if libc::tcsetattr(file.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+            }
+
+            return Ok(Self { file });
+        }
+    }
+
+    impl super::Transport for PtyTransport {
+        fn read_available(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            return match self.file.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(0),
+                r => r,
+            };
+        }
+
+        fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<usize> {
+            let mut fds = libc::pollfd {
+                fd: self.file.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let timeout_ms = timeout.as_millis().min(i32::MAX as u128) as i32;
+
+            if unsafe { libc::poll(&mut fds, 1, timeout_ms) } < 0 {
+                return match io::Error::last_os_error() {
+                    e if e.kind() == io::ErrorKind::Interrupted => Ok(0),
+                    e => Err(e),
+                };
+            }
+
+            return self.read_available(buf);
+        }
+
+        fn write(&mut self, mut buf: &[u8]) -> io::Result<()> {
+            while !buf.is_empty() {
+                match self.file.write(buf) {
+                    Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+                    Ok(n) => buf = &buf[n..],
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => std::thread::yield_now(),
+                    Err(e) => return Err(e),
+                }
+            }
+            return Ok(());
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            return self.file.flush();
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use super::MAX_FRAME_LEN;
+
+/// Bursts are held back at most this long to be sent together.
+pub const BATCH_LATENCY_US: u64 = 2_000;
+
+/// Joins consecutive bursts of one sensor into batches of up to `MAX_BATCH` bursts.
+///
+/// Every burst goes in a batch, so it reaches the host with the board time.
+/// Other messages break the batch and pass through unchanged, so the order is kept.
+pub struct Batcher {
+    batch: Option<(protocol::Batch, u64)>,
+}
+
+impl Batcher {
+    pub fn new() -> Self {
+        return Self { batch: None };
+    }
+
+    /// Takes message going to the host, returns messages ready to be sent, in order.
+    pub fn push(
+        &mut self,
+        message: protocol::Message,
+        now_us: u64,
+    ) -> protocol::Vec<protocol::Message, 2> {
+        let mut out = protocol::Vec::new();
+
+        if !matches!(
+            message,
+            protocol::Message::B16(..) | protocol::Message::B32(..)
+        ) {
+            out.extend(self.flush());
+            out.push(message).ok();
+            return out;
+        }
+
+        if let Some((batch, _)) = self.batch.as_mut() {
+            let mut grown = *batch;
+            if grown.push(message) && fits(&grown) {
+                *batch = grown;
+                if batch.len() == protocol::MAX_BATCH {
+                    out.extend(self.flush());
+                }
+                return out;
+            }
+            out.extend(self.flush());
+        }
+
+        self.batch = protocol::Batch::new(message, now_us as u32).map(|b| (b, now_us));
+        return out;
+    }
+
+    /// Batch waiting longer than `BATCH_LATENCY_US`, if there is one.
+    pub fn poll(&mut self, now_us: u64) -> Option<protocol::Message> {
+        return match self.batch {
+            Some((_, started)) if now_us >= started + BATCH_LATENCY_US => self.flush(),
+            _ => None,
+        };
+    }
+
+    /// Batch collected so far.
+    pub fn flush(&mut self) -> Option<protocol::Message> {
+        let (batch, _) = self.batch.take()?;
+        return Some(protocol::Message::BAT(batch));
+    }
+}
+
+impl Default for Batcher {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+fn fits(batch: &protocol::Batch) -> bool {
+    return protocol::to_vec_cobs::<_, MAX_FRAME_LEN>(&protocol::Message::BAT(*batch)).is_ok();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn burst(cntr: u16) -> protocol::Message {
+        let mut words = [0; 10];
+        words[8] = cntr;
+        return protocol::Message::B16(0, protocol::cfg::BurstSel::Sel0, words.into());
+    }
+
+    fn burst32(cntr: u16) -> protocol::Message {
+        let mut words = [0xffff; 16];
+        words[14] = cntr;
+        return protocol::Message::B32(1, protocol::cfg::BurstSel::Sel0, words.into());
+    }
+
+    fn unbatch(message: &protocol::Message) -> (u32, Vec<protocol::Message>) {
+        let protocol::Message::BAT(batch) = message else {
+            panic!("expected batch, got {:?}", message);
+        };
+        return (batch.timestamp_us, batch.messages().collect());
+    }
+
+    #[test]
+    fn batches_consecutive_bursts() {
+        let mut batcher = Batcher::new();
+
+        assert!(batcher.push(burst(1), 0).is_empty());
+        assert!(batcher.push(burst(2), 10).is_empty());
+        assert!(batcher.poll(BATCH_LATENCY_US - 1).is_none());
+
+        // gap in the counter starts new batch
+        let out = batcher.push(burst(4), 20);
+        assert_eq!(unbatch(&out[0]), (0, vec![burst(1), burst(2)]));
+
+        let frame = protocol::to_vec_cobs::<_, MAX_FRAME_LEN>(&out[0]).unwrap();
+        let mut frame = frame.to_vec();
+        assert_eq!(
+            protocol::from_bytes_cobs::<protocol::Message>(&mut frame),
+            Ok(out[0])
+        );
+
+        // other messages keep their place
+        let out = batcher.push(protocol::Message::RST, 30);
+        assert_eq!(unbatch(&out[0]), (20, vec![burst(4)]));
+        assert_eq!(out[1], protocol::Message::RST);
+
+        // even single burst carries the board time
+        batcher.push(burst(5), 40);
+        let out = batcher.poll(40 + BATCH_LATENCY_US).unwrap();
+        assert_eq!(unbatch(&out), (40, vec![burst(5)]));
+    }
+
+    #[test]
+    fn batches_32_bit_bursts() {
+        let mut batcher = Batcher::new();
+
+        let mut out = Vec::new();
+        for cntr in 0..protocol::MAX_BATCH as u16 {
+            out.extend(batcher.push(burst32(cntr), cntr as u64));
+        }
+        // full batch goes right away, other kind of burst does not join it
+        assert_eq!(out.len(), 1);
+        assert_eq!(
+            unbatch(&out[0]).1,
+            (0..protocol::MAX_BATCH as u16)
+                .map(burst32)
+                .collect::<Vec<_>>()
+        );
+
+        batcher.push(burst32(4), 10);
+        let out = batcher.push(burst(5), 11);
+        assert_eq!(unbatch(&out[0]), (10, vec![burst32(4)]));
+        assert_eq!(unbatch(&batcher.flush().unwrap()), (11, vec![burst(5)]));
+    }
+}
@@ -0,0 +1,32 @@
+use protocol::adis::msc_ctrl::*;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Config {
+    pub burst_enabled: bool,
+    pub msc_ctrl: MscCtrl,
+}
+
+impl Config {
+    /// Messages writing `msc_ctrl` item by item, `burst_enabled` is left to the caller to apply last.
+    pub fn msc_ctrl_messages(&self) -> [protocol::cfg::CFG; 8] {
+        return [
+            protocol::cfg::CFG::Burst32(self.msc_ctrl.burst32),
+            protocol::cfg::CFG::BurstSel(self.msc_ctrl.burst_sel),
+            protocol::cfg::CFG::LinearAccelerationCompensation(self.msc_ctrl.lac),
+            protocol::cfg::CFG::PointOfPercussionAlignment(self.msc_ctrl.popa),
+            protocol::cfg::CFG::SensorBandwidth(self.msc_ctrl.bw),
+            protocol::cfg::CFG::SyncMode(self.msc_ctrl.sync_mode),
+            protocol::cfg::CFG::SyncPolarity(self.msc_ctrl.sync_pol),
+            protocol::cfg::CFG::DataReadyPolarity(self.msc_ctrl.dr_pol),
+        ];
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Self {
+            burst_enabled: false,
+            msc_ctrl: MscCtrl::default(),
+        };
+    }
+}
@@ -0,0 +1,194 @@
+use protocol::{Health, SensorIndex};
+
+use super::{Clock, Firmware, Imu, Settings};
+
+/// Longest data ready period, the highest decimation gets the sensor down to about 1 Hz.
+pub const DR_TIMEOUT_US: u64 = 1_500_000;
+/// All zero bursts in a row, after which the sensor is considered stuck.
+pub const ZERO_BURSTS_LIMIT: u8 = 16;
+/// Start-up time of the sensor after reset, its configuration is restored afterwards.
+pub const RESET_RECOVERY_US: u64 = 300_000;
+/// Resets in a row without any good burst, the sensor is left alone afterwards.
+pub const MAX_RESETS: u8 = 3;
+
+const SENSORS: usize = 2;
+
+/// Watches the sensors with bursts enabled, resets those that stop working and restores their configuration.
+///
+/// Everything it finds out is reported by `Message::Health`.
+pub struct Supervisor {
+    sensors: [Watch; SENSORS],
+}
+
+#[derive(Clone, Copy)]
+struct Watch {
+    state: State,
+    last_data_ready_us: Option<u64>,
+    zero_bursts: u8,
+    resets: u8,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    Running,
+    /// Reset pulsed, the configuration is restored once the sensor starts up.
+    Resetting {
+        since_us: u64,
+        settings: Settings,
+    },
+    /// Resets did not help, waiting for the sensor to come back on its own.
+    Failed,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        return Self {
+            sensors: [Watch {
+                state: State::Running,
+                last_data_ready_us: None,
+                zero_bursts: 0,
+                resets: 0,
+            }; SENSORS],
+        };
+    }
+
+    /// Forgets sensors being reset or given up, the host configures them on its own.
+    pub fn clear(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Clears the supervision when the host message changes configuration of the sensors.
+    pub fn host_message(&mut self, message: &protocol::Message) {
+        if matches!(
+            message,
+            protocol::Message::CFG(..)
+                | protocol::Message::RST
+                | protocol::Message::STO(protocol::Store::Load)
+        ) {
+            self.clear();
+        }
+    }
+
+    /// Notes data ready edge of the sensor.
+    pub fn data_ready(&mut self, sensor: SensorIndex, now_us: u64) {
+        if let Some(watch) = self.sensors.get_mut(sensor as usize) {
+            watch.last_data_ready_us = Some(now_us);
+        }
+    }
+
+    /// Checks burst read from the sensor, returns `Recovered` for the first good one after reset.
+    pub fn burst(&mut self, message: &protocol::Message) -> Option<protocol::Message> {
+        let (sensor, zeros) = match message {
+            protocol::Message::B16(sensor, _, burst) => (*sensor, *burst == Default::default()),
+            protocol::Message::B32(sensor, _, burst) => (*sensor, *burst == Default::default()),
+            _ => return None,
+        };
+        let watch = self.sensors.get_mut(sensor as usize)?;
+
+        if zeros {
+            watch.zero_bursts = watch.zero_bursts.saturating_add(1);
+            return None;
+        }
+
+        watch.zero_bursts = 0;
+        if matches!(watch.state, State::Resetting { .. }) || core::mem::take(&mut watch.resets) == 0
+        {
+            return None;
+        }
+        watch.state = State::Running;
+        return Some(protocol::Message::Health(sensor, Health::Recovered));
+    }
+
+    /// Resets stuck sensors and restores the configuration of those reset before, returns the health reports.
+    ///
+    /// Talks to the sensors, the SPI must not be busy.
+    pub fn poll<IMU0, IMU1, CLK>(
+        &mut self,
+        firmware: &mut Firmware<IMU0, IMU1, CLK>,
+        now_us: u64,
+    ) -> protocol::Vec<protocol::Message, SENSORS>
+    where
+        IMU0: Imu,
+        IMU1: Imu,
+        CLK: Clock,
+    {
+        let mut reports = protocol::Vec::new();
+
+        for (index, watch) in self.sensors.iter_mut().enumerate() {
+            let sensor = index as SensorIndex;
+            let (problem, settings) = match watch.state {
+                State::Running => {
+                    let Some(config) = firmware.config(sensor).filter(|c| c.burst_enabled).copied()
+                    else {
+                        // nothing is expected from the sensor, the timeout starts with the bursts
+                        watch.last_data_ready_us = None;
+                        watch.zero_bursts = 0;
+                        continue;
+                    };
+                    let last = *watch.last_data_ready_us.get_or_insert(now_us);
+
+                    let problem = if watch.zero_bursts >= ZERO_BURSTS_LIMIT {
+                        Health::ZeroBursts
+                    } else if now_us.saturating_sub(last) > DR_TIMEOUT_US {
+                        Health::DataReadyTimeout
+                    } else {
+                        continue;
+                    };
+
+                    let mut settings = Settings {
+                        sensors: [None; SENSORS],
+                    };
+                    settings.sensors[index] = Some(config);
+                    (Some(problem), settings)
+                }
+
+                State::Resetting { since_us, settings } => {
+                    if now_us.saturating_sub(since_us) < RESET_RECOVERY_US {
+                        continue;
+                    }
+
+                    // the sensor gets the full timeout to show up again
+                    watch.last_data_ready_us = Some(now_us);
+                    watch.zero_bursts = 0;
+                    if firmware.apply(&settings) {
+                        watch.state = State::Running;
+                        continue;
+                    }
+
+                    // configuration not accepted, the sensor is reset once more
+                    (None, settings)
+                }
+
+                State::Failed => continue,
+            };
+
+            if watch.resets >= MAX_RESETS {
+                watch.state = State::Failed;
+                reports
+                    .push(protocol::Message::Health(sensor, Health::Failed))
+                    .ok();
+                continue;
+            }
+
+            firmware.reset_sensor(sensor);
+            watch.resets += 1;
+            watch.state = State::Resetting {
+                since_us: now_us,
+                settings,
+            };
+            if let Some(problem) = problem {
+                reports
+                    .push(protocol::Message::Health(sensor, problem))
+                    .ok();
+            }
+        }
+
+        return reports;
+    }
+}
+
+impl Default for Supervisor {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
@@ -0,0 +1,148 @@
+/// Period of the blink patterns, only the USB one is faster.
+const PATTERN_PERIOD_US: u64 = 1_000_000;
+const BLINK_US: u64 = 100_000;
+/// SPI failure is shown this long after the last failed transfer.
+pub const SPI_FAILURE_HOLD_US: u64 = 5_000_000;
+
+/// What the board LED shows, when more things apply the one listed last wins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Indication {
+    /// Short blink every second, no sensor has bursts enabled.
+    Idle,
+    /// Solid on, bursts are being sent.
+    Streaming,
+    /// Two short blinks every second, DIAG_STAT of the last burst has some bits set.
+    SensorFault,
+    /// Four short blinks every second, stored configuration was not accepted by the sensors.
+    SettingsFailed,
+    /// Three short blinks every second, transfers with the sensors failed lately.
+    SpiFailure,
+    /// Fast blinking, the host did not configure the USB device.
+    UsbNotConfigured,
+}
+
+impl Indication {
+    /// Whether the LED is on at the time.
+    pub fn is_on(&self, now_us: u64) -> bool {
+        let blinks = match self {
+            Self::Idle => 1,
+            Self::Streaming => return true,
+            Self::SensorFault => 2,
+            Self::SettingsFailed => 4,
+            Self::SpiFailure => 3,
+            Self::UsbNotConfigured => return (now_us / BLINK_US) & 1 == 0,
+        };
+
+        // blinks are as long as the gaps between them
+        let slot = (now_us % PATTERN_PERIOD_US) / BLINK_US;
+        return slot & 1 == 0 && slot < 2 * blinks;
+    }
+}
+
+/// Picks the indication from `Status` of the board, which tells failed transfers only by their count.
+pub struct StatusLed {
+    spi_errors: u32,
+    spi_failure_us: Option<u64>,
+}
+
+impl StatusLed {
+    pub fn new() -> Self {
+        return Self {
+            spi_errors: 0,
+            spi_failure_us: None,
+        };
+    }
+
+    pub fn indication(
+        &mut self,
+        status: &protocol::Status,
+        usb_configured: bool,
+        now_us: u64,
+    ) -> Indication {
+        if status.spi_errors != self.spi_errors {
+            self.spi_errors = status.spi_errors;
+            self.spi_failure_us = Some(now_us);
+        }
+        let spi_failure = self
+            .spi_failure_us
+            .is_some_and(|t| now_us.saturating_sub(t) < SPI_FAILURE_HOLD_US);
+        let sensors = status.sensors.iter().flatten();
+
+        return if !usb_configured {
+            Indication::UsbNotConfigured
+        } else if spi_failure {
+            Indication::SpiFailure
+        } else if status.settings_failed {
+            Indication::SettingsFailed
+        } else if sensors.clone().any(|s| s.diag_stat != 0) {
+            Indication::SensorFault
+        } else if sensors.clone().any(|s| s.burst_enabled) {
+            Indication::Streaming
+        } else {
+            Indication::Idle
+        };
+    }
+}
+
+impl Default for StatusLed {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn blinks(indication: Indication) -> usize {
+        let on: Vec<bool> = (0..PATTERN_PERIOD_US / BLINK_US)
+            .map(|i| indication.is_on(i * BLINK_US))
+            .collect();
+        return on.windows(2).filter(|w| !w[0] && w[1]).count() + on[0] as usize;
+    }
+
+    #[test]
+    fn most_serious_indication_wins() {
+        assert_eq!(blinks(Indication::Idle), 1);
+        assert_eq!(blinks(Indication::SensorFault), 2);
+        assert_eq!(blinks(Indication::SpiFailure), 3);
+        assert_eq!(blinks(Indication::SettingsFailed), 4);
+        assert_eq!(blinks(Indication::UsbNotConfigured), 5);
+        assert!((0..10).all(|i| Indication::Streaming.is_on(i * BLINK_US)));
+
+        let mut led = StatusLed::new();
+        let mut status = protocol::Status::default();
+        assert_eq!(
+            led.indication(&status, false, 0),
+            Indication::UsbNotConfigured
+        );
+        assert_eq!(led.indication(&status, true, 0), Indication::Idle);
+
+        status.sensors[0] = Some(protocol::SensorStatus {
+            burst_enabled: true,
+            ..Default::default()
+        });
+        assert_eq!(led.indication(&status, true, 0), Indication::Streaming);
+
+        status.sensors[0].as_mut().unwrap().diag_stat = 1 << 3;
+        assert_eq!(led.indication(&status, true, 0), Indication::SensorFault);
+
+        // SPI failure shows for a while after the count goes up
+        status.spi_errors = 1;
+        assert_eq!(led.indication(&status, true, 1_000), Indication::SpiFailure);
+        assert_eq!(
+            led.indication(&status, true, SPI_FAILURE_HOLD_US),
+            Indication::SpiFailure
+        );
+        assert_eq!(
+            led.indication(&status, true, SPI_FAILURE_HOLD_US + 1_000),
+            Indication::SensorFault
+        );
+
+        status.settings_failed = true;
+        assert_eq!(
+            led.indication(&status, true, SPI_FAILURE_HOLD_US + 1_000),
+            Indication::SettingsFailed
+        );
+    }
+}
@@ -0,0 +1,949 @@
+#![cfg_attr(not(test), no_std)]
+
+pub mod batch;
+pub mod config;
+pub mod health;
+pub mod led;
+pub mod sensor;
+pub mod settings;
+pub mod spi;
+pub mod tx;
+
+pub use batch::Batcher;
+pub use config::Config;
+pub use health::Supervisor;
+pub use led::StatusLed;
+pub use protocol;
+pub use sensor::{Imu, NoImu, Sensor};
+pub use settings::{FlashError, Settings, SettingsFlash, SettingsStore};
+pub use spi::{AdisSpi, BusError, HardwareCs, SpiClock};
+pub use tx::TxQueue;
+
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+pub const SERIAL_PACKET_SIZE: usize = 64;
+/// Longest frame sent to the host, batches of 32 bit bursts span more serial packets.
+pub const MAX_FRAME_LEN: usize = 256;
+pub const MAX_RESPONSES: usize = 8;
+/// Shortest frame has 3 bytes, the variant tag, COBS overhead and the delimiter.
+pub const MAX_MESSAGES: usize = SERIAL_PACKET_SIZE / 3;
+pub const BURST_QUEUE_LEN: usize = 32;
+pub const COMMAND_QUEUE_LEN: usize = 8;
+
+/// Words of 16 bit and 32 bit burst following the burst request.
+pub const BURST16_WORDS: usize = 10;
+pub const BURST32_WORDS: usize = 16;
+
+/// Period of `Status` sent as heartbeat.
+pub const STATUS_PERIOD_US: u64 = 1_000_000;
+
+const RESET_PULSE_US: u64 = 50;
+const CONFIG_PROPAGATION_US: u64 = 1_000;
+
+/// Bursts read after data ready edges, waiting to be sent to the host, responses to host messages go along.
+///
+/// Single producer single consumer queue without locks, the acquisition fills it and the USB side drains it.
+pub type BurstQueue = heapless::spsc::Queue<protocol::Message, BURST_QUEUE_LEN>;
+pub type BurstProducer<'a> = heapless::spsc::Producer<'a, protocol::Message, BURST_QUEUE_LEN>;
+pub type BurstConsumer<'a> = heapless::spsc::Consumer<'a, protocol::Message, BURST_QUEUE_LEN>;
+
+/// Host messages decoded on the USB side, waiting for the acquisition to handle them.
+pub type CommandQueue = heapless::spsc::Queue<protocol::Message, COMMAND_QUEUE_LEN>;
+pub type CommandProducer<'a> = heapless::spsc::Producer<'a, protocol::Message, COMMAND_QUEUE_LEN>;
+pub type CommandConsumer<'a> = heapless::spsc::Consumer<'a, protocol::Message, COMMAND_QUEUE_LEN>;
+
+/// Monotonic time source counting microseconds, used for the SPI stall time and short delays.
+pub trait Clock {
+    fn now_us(&self) -> u64;
+}
+
+fn wait_until(clock: &dyn Clock, deadline_us: u64) {
+    while clock.now_us() < deadline_us {}
+}
+
+/// Decodes host messages from COBS frames, a frame may be split between more calls.
+pub struct Decoder {
+    cobs_buf: protocol::CobsAccumulator<256>,
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        return Self {
+            cobs_buf: protocol::CobsAccumulator::new(),
+        };
+    }
+
+    /// Messages of the frames completed by the bytes, broken frames are skipped.
+    pub fn feed(&mut self, bytes: &[u8]) -> protocol::Vec<protocol::Message, MAX_MESSAGES> {
+        let mut window = bytes;
+        let mut messages = protocol::Vec::new();
+
+        'cobs: while !window.is_empty() {
+            window = match self.cobs_buf.feed::<protocol::Message>(window) {
+                protocol::FeedResult::Consumed => break 'cobs,
+                protocol::FeedResult::OverFull(new_wind) => new_wind,
+                protocol::FeedResult::DeserError(new_wind) => new_wind,
+                protocol::FeedResult::Success { data, remaining } => {
+                    messages.push(data).ok();
+                    remaining
+                }
+            };
+        }
+
+        return messages;
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+/// Board independent part of the firmware, handles host messages and talks to the sensors.
+///
+/// Boards carry one sensor, optionally a second one on another SPI bus, messages address them by index.
+pub struct Firmware<IMU0, IMU1, CLK> {
+    sensors: (IMU0, IMU1),
+    clock: CLK,
+    decoder: Decoder,
+    stats: Stats,
+}
+
+/// Counters reported by `Status`.
+#[derive(Default)]
+struct Stats {
+    bursts: u32,
+    dropped: u32,
+    spi_errors: u32,
+    diag_stat: [u16; 2],
+    next_status_us: u64,
+    settings_failed: bool,
+}
+
+impl<SPI, RST, CLK> Firmware<Sensor<SPI, RST>, NoImu, CLK>
+where
+    SPI: Transfer<u16>,
+    RST: OutputPin,
+    CLK: Clock,
+{
+    pub fn new(spi: SPI, n_rst: RST, clock: CLK) -> Self {
+        return Self {
+            sensors: (Sensor::new(spi, n_rst), NoImu),
+            clock,
+            decoder: Decoder::new(),
+            stats: Stats::default(),
+        };
+    }
+}
+
+impl<IMU0, CLK> Firmware<IMU0, NoImu, CLK> {
+    /// Adds sensor with index 1.
+    pub fn with_second_sensor<SPI, RST>(
+        self,
+        spi: SPI,
+        n_rst: RST,
+    ) -> Firmware<IMU0, Sensor<SPI, RST>, CLK>
+    where
+        SPI: Transfer<u16>,
+        RST: OutputPin,
+    {
+        return Firmware {
+            sensors: (self.sensors.0, Sensor::new(spi, n_rst)),
+            clock: self.clock,
+            decoder: self.decoder,
+            stats: self.stats,
+        };
+    }
+}
+
+impl<IMU0, IMU1, CLK> Firmware<IMU0, IMU1, CLK>
+where
+    IMU0: Imu,
+    IMU1: Imu,
+    CLK: Clock,
+{
+    /// Configuration of the sensor, `None` if there is no such sensor.
+    pub fn config(&self, sensor: protocol::SensorIndex) -> Option<&Config> {
+        return match sensor {
+            0 => self.sensors.0.config(),
+            1 => self.sensors.1.config(),
+            _ => None,
+        };
+    }
+
+    /// SPI timing of the sensor, `None` if there is no such sensor.
+    pub fn spi_timing(&self, sensor: protocol::SensorIndex) -> Option<&protocol::SpiTiming> {
+        return match sensor {
+            0 => self.sensors.0.spi_timing(),
+            1 => self.sensors.1.spi_timing(),
+            _ => None,
+        };
+    }
+
+    /// Decodes bytes received from the host and handles every complete message in them.
+    pub fn receive(&mut self, bytes: &[u8]) -> protocol::Vec<protocol::Message, MAX_RESPONSES> {
+        let mut response = protocol::Vec::new();
+        for message in self.decoder.feed(bytes) {
+            if let Some(r) = self.handle(message) {
+                response.push(r).ok();
+            }
+        }
+
+        return response;
+    }
+
+    /// Handles a single host message, returns the response to be sent back (if any).
+    pub fn handle(&mut self, message: protocol::Message) -> Option<protocol::Message> {
+        return match message {
+            // ack the message only if the sensor accepted the change
+            protocol::Message::CFG(sensor, cfg) => {
+                let clock = &self.clock;
+                let sensor = match sensor {
+                    0 => &mut self.sensors.0 as &mut dyn Imu,
+                    1 => &mut self.sensors.1,
+                    _ => return None,
+                };
+                sensor.configure(cfg, clock).then_some(message)
+            }
+
+            protocol::Message::RQR(rqr) => self
+                .sensors
+                .0
+                .request_response(rqr, &self.clock)
+                .ok()
+                .map(protocol::Message::RQR),
+
+            protocol::Message::RST => {
+                self.sensors.0.reset(&self.clock);
+                self.sensors.1.reset(&self.clock);
+
+                Some(message)
+            }
+
+            protocol::Message::B16(..) => None,
+
+            protocol::Message::B32(..) => None,
+
+            protocol::Message::ERR(..) => None,
+
+            protocol::Message::BAT(..) => None,
+
+            protocol::Message::Overflow { .. } => None,
+
+            // the board keeps the store, see `handle_with_store`
+            protocol::Message::STO(..) => None,
+
+            protocol::Message::Health(..) => None,
+
+            protocol::Message::StatusRequest => Some(protocol::Message::Status(self.status())),
+
+            protocol::Message::Status(..) => None,
+
+            // rebooting is up to the board
+            protocol::Message::EnterBootloader => None,
+
+            protocol::Message::SpiTiming(sensor, timing) => {
+                let applied = match sensor {
+                    0 => self.sensors.0.set_spi_timing(timing),
+                    1 => self.sensors.1.set_spi_timing(timing),
+                    _ => None,
+                };
+                applied
+                    .or(self.spi_timing(sensor).copied())
+                    .map(|t| protocol::Message::SpiTiming(sensor, t))
+            }
+        };
+    }
+
+    /// Handles host message as `handle`, messages about the stored configuration use the store.
+    pub fn handle_with_store<F: SettingsFlash>(
+        &mut self,
+        message: protocol::Message,
+        store: &mut SettingsStore<F>,
+    ) -> Option<protocol::Message> {
+        let protocol::Message::STO(command) = message else {
+            return self.handle(message);
+        };
+
+        let done = match command {
+            protocol::Store::Save => store.save(&self.settings()).is_ok(),
+            protocol::Store::Load => store.load().is_some_and(|s| self.apply_stored(&s)),
+            protocol::Store::Erase => store.erase().is_ok(),
+        };
+        return done.then_some(message);
+    }
+
+    /// Configuration of all sensors, as it would be stored.
+    pub fn settings(&self) -> Settings {
+        return Settings {
+            sensors: [self.config(0).copied(), self.config(1).copied()],
+        };
+    }
+
+    /// Configures the sensors present as in the settings, bursts are enabled last.
+    ///
+    /// Returns whether the sensors accepted all of it.
+    pub fn apply(&mut self, settings: &Settings) -> bool {
+        let mut accepted = true;
+        let present = |fw: &Self, sensor: usize| {
+            return settings.sensors[sensor]
+                .filter(|_| fw.config(sensor as protocol::SensorIndex).is_some());
+        };
+
+        for sensor in 0..settings.sensors.len() {
+            if let Some(config) = present(self, sensor) {
+                for cfg in config.msc_ctrl_messages() {
+                    accepted &= self
+                        .handle(protocol::Message::CFG(sensor as u8, cfg))
+                        .is_some();
+                }
+            }
+        }
+        for sensor in 0..settings.sensors.len() {
+            if let Some(config) = present(self, sensor) {
+                let cfg = protocol::cfg::CFG::BurstEn(config.burst_enabled);
+                accepted &= self
+                    .handle(protocol::Message::CFG(sensor as u8, cfg))
+                    .is_some();
+            }
+        }
+
+        return accepted;
+    }
+
+    /// Applies stored settings as `apply` once the sensors started after power up, `Status` tells the failure.
+    pub fn apply_stored(&mut self, settings: &Settings) -> bool {
+        wait_until(&self.clock, health::RESET_RECOVERY_US);
+        let applied = self.apply(settings);
+        self.stats.settings_failed = !applied;
+        return applied;
+    }
+
+    /// Pulses the reset pin of the sensor, its configuration returns to default.
+    pub fn reset_sensor(&mut self, sensor: protocol::SensorIndex) {
+        match sensor {
+            0 => self.sensors.0.reset(&self.clock),
+            1 => self.sensors.1.reset(&self.clock),
+            _ => {}
+        }
+    }
+
+    /// Reads one burst from the sensor, meant to be called after its data ready edge.
+    pub fn burst(&mut self, sensor: protocol::SensorIndex) -> Result<protocol::Message, BusError> {
+        let burst = match sensor {
+            0 => self.sensors.0.burst(0, &self.clock),
+            1 => self.sensors.1.burst(1, &self.clock),
+            _ => Err(BusError::NoSensor),
+        };
+        return self.count_burst(burst);
+    }
+
+    /// Requests burst of the sensor, returns how many words have to be read after the stall time.
+    pub fn start_burst(&mut self, sensor: protocol::SensorIndex) -> Result<usize, BusError> {
+        let len = match sensor {
+            0 => self.sensors.0.start_burst(&self.clock),
+            1 => self.sensors.1.start_burst(&self.clock),
+            _ => Err(BusError::NoSensor),
+        };
+        if len.is_err() {
+            self.stats.spi_errors = self.stats.spi_errors.wrapping_add(1);
+        }
+        return len;
+    }
+
+    /// Burst message from words read after `start_burst`.
+    pub fn finish_burst(
+        &mut self,
+        sensor: protocol::SensorIndex,
+        words: &[u16],
+    ) -> Result<protocol::Message, BusError> {
+        let burst = match sensor {
+            0 => self.sensors.0.finish_burst(0, words, &self.clock),
+            1 => self.sensors.1.finish_burst(1, words, &self.clock),
+            _ => Err(BusError::NoSensor),
+        };
+        return self.count_burst(burst);
+    }
+
+    fn count_burst(
+        &mut self,
+        burst: Result<protocol::Message, BusError>,
+    ) -> Result<protocol::Message, BusError> {
+        let (sensor, diag_stat) = match burst {
+            Ok(protocol::Message::B16(sensor, _, b)) => {
+                (sensor, protocol::adis::burstmem::BurstMemory::diag_stat(&b))
+            }
+            Ok(protocol::Message::B32(sensor, _, b)) => {
+                (sensor, protocol::adis::burstmem::BurstMemory::diag_stat(&b))
+            }
+            _ => {
+                self.stats.spi_errors = self.stats.spi_errors.wrapping_add(1);
+                return burst;
+            }
+        };
+
+        self.stats.bursts = self.stats.bursts.wrapping_add(1);
+        if let Some(d) = self.stats.diag_stat.get_mut(sensor as usize) {
+            *d = diag_stat;
+        }
+        return burst;
+    }
+
+    /// Notes messages dropped by the board, because there was no room for them.
+    pub fn count_dropped(&mut self, dropped: u32) {
+        self.stats.dropped = self.stats.dropped.wrapping_add(dropped);
+    }
+
+    /// Current state of the board, `usb_overflows` is left to the USB side, see `TxQueue`.
+    pub fn status(&self) -> protocol::Status {
+        let sensor = |sensor: protocol::SensorIndex| {
+            return self.config(sensor).map(|c| protocol::SensorStatus {
+                burst_enabled: c.burst_enabled,
+                msc_ctrl: c.msc_ctrl,
+                diag_stat: self.stats.diag_stat[sensor as usize],
+            });
+        };
+
+        return protocol::Status {
+            uptime_ms: (self.clock.now_us() / 1_000) as u32,
+            sensors: [sensor(0), sensor(1)],
+            bursts: self.stats.bursts,
+            dropped: self.stats.dropped,
+            spi_errors: self.stats.spi_errors,
+            usb_overflows: 0,
+            settings_failed: self.stats.settings_failed,
+        };
+    }
+
+    /// `Status` once every `STATUS_PERIOD_US`, the host knows the board is alive even without bursts.
+    pub fn poll_status(&mut self) -> Option<protocol::Message> {
+        let now_us = self.clock.now_us();
+        if now_us < self.stats.next_status_us {
+            return None;
+        }
+
+        self.stats.next_status_us = now_us + STATUS_PERIOD_US;
+        return Some(protocol::Message::Status(self.status()));
+    }
+
+    pub fn clock(&self) -> &CLK {
+        return &self.clock;
+    }
+
+    /// Reads burst into the queue if the sensor has bursts enabled, returns whether no burst was lost.
+    pub fn acquire(&mut self, sensor: protocol::SensorIndex, queue: &mut BurstProducer) -> bool {
+        if !self.config(sensor).is_some_and(|c| c.burst_enabled) {
+            return true;
+        }
+
+        // no point reading the sensor when there is no room left
+        if !queue.ready() {
+            self.count_dropped(1);
+            return false;
+        }
+
+        return match self.burst(sensor) {
+            Ok(burst) => queue.enqueue(burst).is_ok(),
+            Err(_) => false,
+        };
+    }
+}
+
+/// Encodes messages into COBS frames fitting into a single serial packet, messages that do not fit are dropped.
+pub fn encode(messages: &[protocol::Message]) -> protocol::Vec<u8, SERIAL_PACKET_SIZE> {
+    let mut out = protocol::Vec::new();
+    for m in messages {
+        if let Ok(frame) = protocol::to_vec_cobs::<_, SERIAL_PACKET_SIZE>(m) {
+            if out.extend_from_slice(&frame).is_err() {
+                break;
+            }
+        }
+    }
+    return out;
+}
+
+/// USB serial number of the board, unique ID of its flash chip written as hex digits.
+pub fn serial_number(unique_id: &[u8; 8]) -> [u8; 16] {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+
+    let mut out = [0; 16];
+    for (i, byte) in unique_id.iter().enumerate() {
+        out[2 * i] = HEX[(byte >> 4) as usize];
+        out[2 * i + 1] = HEX[(byte & 0x0f) as usize];
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use core::cell::Cell;
+    use core::convert::Infallible;
+    use protocol::adis;
+
+    /// Imu mock holding only the msc_ctrl register, answers requests on the following transfer.
+    struct MockSpi {
+        msc_ctrl: u16,
+        accept_writes: bool,
+        output: u16,
+        written: Vec<u16>,
+    }
+
+    impl MockSpi {
+        fn new() -> Self {
+            return Self {
+                msc_ctrl: adis::msc_ctrl::MscCtrl::default().into(),
+                accept_writes: true,
+                output: 0,
+                written: Vec::new(),
+            };
+        }
+    }
+
+    impl Transfer<u16> for MockSpi {
+        type Error = Infallible;
+
+        fn transfer<'w>(&mut self, words: &'w mut [u16]) -> Result<&'w [u16], Infallible> {
+            for w in words.iter_mut() {
+                let input = *w;
+                *w = self.output;
+                self.written.push(input);
+
+                let address = ((input >> 8) & 0x7F) as u8;
+                self.output = 0;
+                if input & 0x8000 != 0 && self.accept_writes {
+                    match address {
+                        a if a == adis::memorymap::MSC_CTRL => {
+                            self.msc_ctrl = (self.msc_ctrl & 0xFF00) | (input & 0xFF)
+                        }
+                        a if a == adis::memorymap::MSC_CTRL + 1 => {
+                            self.msc_ctrl = (self.msc_ctrl & 0x00FF) | ((input & 0xFF) << 8)
+                        }
+                        _ => (),
+                    }
+                } else if address == adis::memorymap::MSC_CTRL {
+                    self.output = self.msc_ctrl;
+                }
+            }
+            return Ok(words);
+        }
+    }
+
+    /// Clock divided down to 10 kHz steps.
+    impl SpiClock for MockSpi {
+        fn set_frequency(&mut self, frequency_hz: u32) -> u32 {
+            return frequency_hz - frequency_hz % 10_000;
+        }
+    }
+
+    struct MockPin(bool, u32);
+
+    impl OutputPin for MockPin {
+        type Error = Infallible;
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.0 = false;
+            self.1 += 1;
+            return Ok(());
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.0 = true;
+            return Ok(());
+        }
+    }
+
+    struct MockClock(Cell<u64>);
+
+    impl Clock for MockClock {
+        fn now_us(&self) -> u64 {
+            self.0.set(self.0.get() + 1);
+            return self.0.get();
+        }
+    }
+
+    fn firmware(spi: MockSpi) -> Firmware<Sensor<MockSpi, MockPin>, NoImu, MockClock> {
+        return Firmware::new(spi, MockPin(true, 0), MockClock(Cell::new(0)));
+    }
+
+    fn frames(messages: &[protocol::Message]) -> Vec<u8> {
+        return messages
+            .iter()
+            .flat_map(|m| protocol::to_vec_cobs::<_, 64>(m).unwrap())
+            .collect();
+    }
+
+    #[test]
+    fn burst_en_is_acked_once() {
+        let mut fw = firmware(MockSpi::new());
+        let cfg = protocol::Message::CFG(0, protocol::cfg::CFG::BurstEn(true));
+
+        let response = fw.receive(&frames(&[cfg]));
+
+        assert_eq!(response.as_slice(), &[cfg]);
+        assert!(fw.config(0).unwrap().burst_enabled);
+        assert!(fw.sensors.0.spi.spi.written.is_empty());
+    }
+
+    #[test]
+    fn msc_ctrl_change_is_verified() {
+        let cfg = protocol::Message::CFG(
+            0,
+            protocol::cfg::CFG::Burst32(adis::msc_ctrl::Burst32::Enabled),
+        );
+
+        let mut fw = firmware(MockSpi::new());
+        assert_eq!(fw.receive(&frames(&[cfg])).as_slice(), &[cfg]);
+        assert_eq!(
+            fw.config(0).unwrap().msc_ctrl.burst32,
+            adis::msc_ctrl::Burst32::Enabled
+        );
+
+        let mut spi = MockSpi::new();
+        spi.accept_writes = false;
+        let mut fw = firmware(spi);
+        assert!(fw.receive(&frames(&[cfg])).is_empty());
+        assert_eq!(fw.config(0).unwrap(), &Config::default());
+    }
+
+    #[test]
+    fn rst_pulses_reset_pin_and_restores_config() {
+        let mut fw = firmware(MockSpi::new());
+        fw.handle(protocol::Message::CFG(0, protocol::cfg::CFG::BurstEn(true)));
+
+        let response = fw.receive(&frames(&[
+            protocol::Message::RST,
+            protocol::Message::RQR(0x7200),
+        ]));
+
+        assert_eq!(
+            response.as_slice(),
+            &[protocol::Message::RST, protocol::Message::RQR(0)]
+        );
+        assert_eq!(fw.sensors.0.n_rst.1, 1);
+        assert!(fw.sensors.0.n_rst.0);
+        assert_eq!(fw.config(0).unwrap(), &Config::default());
+    }
+
+    #[test]
+    fn sensors_are_addressed_by_index() {
+        let mut fw = firmware(MockSpi::new()).with_second_sensor(MockSpi::new(), MockPin(true, 0));
+        let cfg = protocol::Message::CFG(
+            1,
+            protocol::cfg::CFG::BurstSel(adis::msc_ctrl::BurstSel::Sel1),
+        );
+
+        assert_eq!(fw.receive(&frames(&[cfg])).as_slice(), &[cfg]);
+        assert_eq!(fw.config(0).unwrap(), &Config::default());
+        assert_eq!(
+            fw.config(1).unwrap().msc_ctrl.burst_sel,
+            adis::msc_ctrl::BurstSel::Sel1
+        );
+        assert!(matches!(
+            fw.burst(1),
+            Ok(protocol::Message::B16(1, adis::msc_ctrl::BurstSel::Sel1, _))
+        ));
+
+        let mut single = firmware(MockSpi::new());
+        assert!(single.receive(&frames(&[cfg])).is_empty());
+        assert!(single.config(1).is_none());
+        assert_eq!(single.burst(1), Err(BusError::NoSensor));
+    }
+
+    #[test]
+    fn acquire_fills_queue() {
+        let mut fw = firmware(MockSpi::new());
+        let mut queue = BurstQueue::new();
+        let (mut producer, mut consumer) = queue.split();
+
+        assert!(fw.acquire(0, &mut producer));
+        assert!(consumer.dequeue().is_none());
+
+        fw.handle(protocol::Message::CFG(0, protocol::cfg::CFG::BurstEn(true)));
+        while producer.ready() {
+            assert!(fw.acquire(0, &mut producer));
+        }
+        assert!(!fw.acquire(0, &mut producer));
+        assert!(fw.acquire(1, &mut producer));
+
+        assert_eq!(consumer.len(), BURST_QUEUE_LEN - 1);
+        assert!(matches!(
+            consumer.dequeue(),
+            Some(protocol::Message::B16(0, ..))
+        ));
+    }
+
+    #[test]
+    fn burst_is_split_for_dma() {
+        let mut fw = firmware(MockSpi::new());
+        let cfg = protocol::Message::CFG(
+            0,
+            protocol::cfg::CFG::Burst32(adis::msc_ctrl::Burst32::Enabled),
+        );
+        fw.handle(cfg);
+        fw.sensors.0.spi.spi.written.clear();
+
+        assert_eq!(fw.start_burst(0), Ok(BURST32_WORDS));
+        assert_eq!(
+            fw.sensors.0.spi.spi.written,
+            [adis::memorymap::request(adis::memorymap::GLOB_CMD)]
+        );
+
+        // registers wait until the burst is read, wrong number of words loses it
+        let rqr = protocol::Message::RQR(adis::memorymap::request(adis::memorymap::MSC_CTRL));
+        assert_eq!(fw.handle(rqr), None);
+        assert_eq!(
+            fw.finish_burst(0, &[0; BURST16_WORDS]),
+            Err(BusError::LengthMismatch)
+        );
+        assert_eq!(
+            fw.finish_burst(0, &[0; BURST32_WORDS]),
+            Err(BusError::LengthMismatch)
+        );
+
+        assert_eq!(fw.start_burst(0), Ok(BURST32_WORDS));
+        assert!(matches!(
+            fw.finish_burst(0, &[0; BURST32_WORDS]),
+            Ok(protocol::Message::B32(0, ..))
+        ));
+        assert_eq!(fw.start_burst(1), Err(BusError::NoSensor));
+    }
+
+    #[test]
+    fn decoder_joins_split_frames() {
+        let mut decoder = Decoder::new();
+        let cfg = protocol::Message::CFG(1, protocol::cfg::CFG::BurstEn(true));
+        let bytes = frames(&[protocol::Message::RST, cfg]);
+
+        assert_eq!(
+            decoder.feed(&bytes[..4]).as_slice(),
+            &[protocol::Message::RST]
+        );
+        assert_eq!(decoder.feed(&bytes[4..]).as_slice(), &[cfg]);
+    }
+
+    #[test]
+    fn settings_are_applied_burst_last() {
+        let mut stored = firmware(MockSpi::new());
+        stored.handle(protocol::Message::CFG(
+            0,
+            protocol::cfg::CFG::Burst32(adis::msc_ctrl::Burst32::Enabled),
+        ));
+        stored.handle(protocol::Message::CFG(0, protocol::cfg::CFG::BurstEn(true)));
+        let mut settings = stored.settings();
+        assert!(settings.sensors[1].is_none());
+
+        // the board has no second sensor, so that part is left out
+        settings.sensors[1] = Some(Config::default());
+        let mut fw = firmware(MockSpi::new());
+        assert!(fw.apply(&settings));
+        assert_eq!(fw.config(0), stored.config(0));
+    }
+
+    #[test]
+    fn stored_settings_wait_for_sensor_start() {
+        let mut config = Config::default();
+        config.msc_ctrl.burst32 = adis::msc_ctrl::Burst32::Enabled;
+        let settings = Settings {
+            sensors: [Some(config), None],
+        };
+
+        let mut fw = firmware(MockSpi::new());
+        assert!(fw.apply_stored(&settings));
+        assert!(fw.clock().now_us() > health::RESET_RECOVERY_US);
+        assert!(!fw.status().settings_failed);
+
+        let mut spi = MockSpi::new();
+        spi.accept_writes = false;
+        let mut fw = firmware(spi);
+        assert!(!fw.apply_stored(&settings));
+        assert!(fw.status().settings_failed);
+    }
+
+    #[test]
+    fn serial_number_from_unique_id() {
+        let serial = serial_number(&[0xe6, 0x60, 0x38, 0xb7, 0x13, 0x2f, 0x0a, 0x2c]);
+        assert_eq!(&serial, b"E66038B7132F0A2C");
+    }
+
+    #[test]
+    fn stuck_sensor_is_reset_and_restored() {
+        let mut fw = firmware(MockSpi::new());
+        let mut supervisor = Supervisor::new();
+        fw.handle(protocol::Message::CFG(0, protocol::cfg::CFG::BurstEn(true)));
+
+        // data ready keeps coming, but the sensor returns only zeros
+        let zeros = protocol::Message::B16(0, protocol::cfg::BurstSel::Sel0, Default::default());
+        for _ in 0..health::ZERO_BURSTS_LIMIT {
+            supervisor.data_ready(0, 0);
+            assert_eq!(supervisor.burst(&zeros), None);
+        }
+        assert_eq!(
+            supervisor.poll(&mut fw, 0).as_slice(),
+            &[protocol::Message::Health(0, protocol::Health::ZeroBursts)]
+        );
+        assert_eq!(fw.sensors.0.n_rst.1, 1);
+        assert!(!fw.config(0).unwrap().burst_enabled);
+
+        // configuration comes back once the sensor starts up, host messages not touching it do not matter
+        for message in [
+            protocol::Message::StatusRequest,
+            protocol::Message::RQR(0x7200),
+            protocol::Message::SpiTiming(0, protocol::SpiTiming::default()),
+            protocol::Message::STO(protocol::Store::Save),
+        ] {
+            supervisor.host_message(&message);
+        }
+        assert!(supervisor
+            .poll(&mut fw, health::RESET_RECOVERY_US - 1)
+            .is_empty());
+        assert!(!fw.config(0).unwrap().burst_enabled);
+        assert!(supervisor
+            .poll(&mut fw, health::RESET_RECOVERY_US)
+            .is_empty());
+        assert!(fw.config(0).unwrap().burst_enabled);
+
+        let mut words = [0; 10];
+        words[8] = 1;
+        let burst = protocol::Message::B16(0, protocol::cfg::BurstSel::Sel0, words.into());
+        assert_eq!(
+            supervisor.burst(&burst),
+            Some(protocol::Message::Health(0, protocol::Health::Recovered))
+        );
+        assert_eq!(supervisor.burst(&burst), None);
+
+        // data ready stops for good, the sensor is given up after few resets
+        let mut now = health::RESET_RECOVERY_US;
+        let mut reports = Vec::new();
+        for _ in 0..8 {
+            now += health::DR_TIMEOUT_US + 1;
+            reports.extend(supervisor.poll(&mut fw, now));
+        }
+        let timeout = protocol::Message::Health(0, protocol::Health::DataReadyTimeout);
+        assert_eq!(
+            reports,
+            [
+                timeout,
+                timeout,
+                timeout,
+                protocol::Message::Health(0, protocol::Health::Failed)
+            ]
+        );
+        assert_eq!(fw.sensors.0.n_rst.1, 1 + health::MAX_RESETS as u32);
+    }
+
+    #[test]
+    fn host_configuration_takes_over_supervision() {
+        let mut fw = firmware(MockSpi::new());
+        let mut supervisor = Supervisor::new();
+        fw.handle(protocol::Message::CFG(0, protocol::cfg::CFG::BurstEn(true)));
+
+        supervisor.poll(&mut fw, 0);
+        assert_eq!(
+            supervisor
+                .poll(&mut fw, health::DR_TIMEOUT_US + 1)
+                .as_slice(),
+            &[protocol::Message::Health(
+                0,
+                protocol::Health::DataReadyTimeout
+            )]
+        );
+
+        // the host configures the sensor meanwhile, its configuration is not overwritten
+        let cfg = protocol::Message::CFG(
+            0,
+            protocol::cfg::CFG::BurstSel(protocol::cfg::BurstSel::Sel1),
+        );
+        supervisor.host_message(&cfg);
+        fw.handle(cfg);
+        assert!(supervisor
+            .poll(
+                &mut fw,
+                health::DR_TIMEOUT_US + health::RESET_RECOVERY_US + 1
+            )
+            .is_empty());
+        assert!(!fw.config(0).unwrap().burst_enabled);
+    }
+
+    #[test]
+    fn status_counts_bursts() {
+        let mut fw = firmware(MockSpi::new());
+        fw.handle(protocol::Message::CFG(0, protocol::cfg::CFG::BurstEn(true)));
+        assert!(fw.burst(0).is_ok());
+        fw.count_dropped(2);
+
+        let Some(protocol::Message::Status(status)) = fw.handle(protocol::Message::StatusRequest)
+        else {
+            panic!("no status");
+        };
+        assert_eq!(status.bursts, 1);
+        assert_eq!(status.dropped, 2);
+        assert_eq!(status.spi_errors, 0);
+        assert!(status.sensors[0].unwrap().burst_enabled);
+        assert_eq!(status.sensors[1], None);
+
+        // heartbeat right away, then once a period
+        assert!(fw.poll_status().is_some());
+        assert!(fw.poll_status().is_none());
+
+        // status with the longest encoding still fits into one packet
+        let sensor = protocol::SensorStatus {
+            burst_enabled: true,
+            msc_ctrl: adis::msc_ctrl::MscCtrl::from(0xFFFF),
+            diag_stat: u16::MAX,
+        };
+        let status = protocol::Status {
+            uptime_ms: u32::MAX,
+            sensors: [Some(sensor); 2],
+            bursts: u32::MAX,
+            dropped: u32::MAX,
+            spi_errors: u32::MAX,
+            usb_overflows: u32::MAX,
+            settings_failed: true,
+        };
+        assert!(
+            protocol::to_vec_cobs::<_, SERIAL_PACKET_SIZE>(&protocol::Message::Status(status))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn spi_timing_is_bounded() {
+        let mut fw = firmware(MockSpi::new());
+        let timing = |frequency_hz, stall_us| protocol::SpiTiming {
+            frequency_hz,
+            stall_us,
+        };
+        let burst_en = protocol::Message::CFG(0, protocol::cfg::CFG::BurstEn(true));
+
+        assert_eq!(
+            fw.handle(protocol::Message::SpiTiming(0, timing(1_234_567, 20))),
+            Some(protocol::Message::SpiTiming(0, timing(1_230_000, 20)))
+        );
+        // rejected timing is answered with the one in use
+        assert_eq!(
+            fw.handle(protocol::Message::SpiTiming(0, timing(1_000_000, 10))),
+            Some(protocol::Message::SpiTiming(0, timing(1_230_000, 20)))
+        );
+        assert_eq!(
+            fw.handle(protocol::Message::SpiTiming(1, timing(1_000_000, 20))),
+            None
+        );
+        assert_eq!(fw.spi_timing(0), Some(&timing(1_230_000, 20)));
+
+        // bursts only at burst speed
+        assert_eq!(fw.handle(burst_en), None);
+        fw.handle(protocol::Message::SpiTiming(0, timing(1_000_000, 20)));
+        assert_eq!(fw.handle(burst_en), Some(burst_en));
+        assert_eq!(
+            fw.handle(protocol::Message::SpiTiming(0, timing(2_000_000, 20))),
+            Some(protocol::Message::SpiTiming(0, timing(1_000_000, 20)))
+        );
+
+        // the bus stays as it is over reset
+        fw.handle(protocol::Message::RST);
+        assert_eq!(fw.spi_timing(0), Some(&timing(1_000_000, 20)));
+    }
+}
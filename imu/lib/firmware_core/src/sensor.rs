@@ -0,0 +1,261 @@
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+use protocol::adis;
+
+use super::{
+    wait_until, AdisSpi, BusError, Clock, Config, SpiClock, BURST16_WORDS, BURST32_WORDS,
+    CONFIG_PROPAGATION_US, RESET_PULSE_US,
+};
+
+/// What the firmware needs from a sensor, sensors on different buses are picked by index through it.
+pub trait Imu {
+    /// Current configuration, `None` if the sensor is not present.
+    fn config(&self) -> Option<&Config>;
+
+    /// Applies single configuration item, returns whether the sensor accepted it.
+    fn configure(&mut self, cfg: protocol::cfg::CFG, clock: &dyn Clock) -> bool;
+
+    /// Pulses the reset pin, the configuration returns to default.
+    fn reset(&mut self, clock: &dyn Clock);
+
+    /// Requests burst after data ready edge, returns how many words the burst has.
+    ///
+    /// The words may be read by other means than the firmware (DMA) after the stall time, see `finish_burst`.
+    fn start_burst(&mut self, clock: &dyn Clock) -> Result<usize, BusError>;
+
+    /// Turns words read after `start_burst` into burst message.
+    fn finish_burst(
+        &mut self,
+        sensor: protocol::SensorIndex,
+        words: &[u16],
+        clock: &dyn Clock,
+    ) -> Result<protocol::Message, BusError>;
+
+    /// Reads one burst, meant to be called after data ready edge.
+    fn burst(
+        &mut self,
+        sensor: protocol::SensorIndex,
+        clock: &dyn Clock,
+    ) -> Result<protocol::Message, BusError>;
+
+    fn request_response(&mut self, data: u16, clock: &dyn Clock) -> Result<u16, BusError>;
+
+    /// SPI timing in use, `None` if the sensor is not present.
+    fn spi_timing(&self) -> Option<&protocol::SpiTiming>;
+
+    /// Applies SPI timing within the bounds, returns the timing in use afterwards, `None` if it was rejected.
+    fn set_spi_timing(&mut self, timing: protocol::SpiTiming) -> Option<protocol::SpiTiming>;
+}
+
+/// ADIS sensor on its own SPI bus and reset pin.
+pub struct Sensor<SPI, RST> {
+    pub(crate) spi: AdisSpi<SPI>,
+    pub(crate) n_rst: RST,
+    config: Config,
+}
+
+impl<SPI, RST> Sensor<SPI, RST>
+where
+    SPI: Transfer<u16>,
+    RST: OutputPin,
+{
+    pub fn new(spi: SPI, n_rst: RST) -> Self {
+        return Self {
+            spi: AdisSpi::new(spi),
+            n_rst,
+            config: Config::default(),
+        };
+    }
+
+    fn burst_len(&self) -> usize {
+        return match self.config.msc_ctrl.burst32 {
+            adis::msc_ctrl::Burst32::Disabled => BURST16_WORDS,
+            adis::msc_ctrl::Burst32::Enabled => BURST32_WORDS,
+        };
+    }
+
+    fn burst_message(
+        &self,
+        sensor: protocol::SensorIndex,
+        words: &[u16],
+    ) -> Result<protocol::Message, BusError> {
+        let burst_sel = self.config.msc_ctrl.burst_sel;
+        return match self.config.msc_ctrl.burst32 {
+            adis::msc_ctrl::Burst32::Disabled => {
+                let imu_out: [u16; BURST16_WORDS] =
+                    words.try_into().map_err(|_| BusError::LengthMismatch)?;
+                Ok(protocol::Message::B16(sensor, burst_sel, imu_out.into()))
+            }
+            adis::msc_ctrl::Burst32::Enabled => {
+                let imu_out: [u16; BURST32_WORDS] =
+                    words.try_into().map_err(|_| BusError::LengthMismatch)?;
+                Ok(protocol::Message::B32(sensor, burst_sel, imu_out.into()))
+            }
+        };
+    }
+}
+
+impl<SPI, RST> Imu for Sensor<SPI, RST>
+where
+    SPI: Transfer<u16> + SpiClock,
+    RST: OutputPin,
+{
+    fn config(&self) -> Option<&Config> {
+        return Some(&self.config);
+    }
+
+    fn configure(&mut self, cfg: protocol::cfg::CFG, clock: &dyn Clock) -> bool {
+        let mut new_config = self.config;
+
+        // switch the various config messages
+        match cfg {
+            // bursts can not be read at full register speed
+            protocol::cfg::CFG::BurstEn(true)
+                if self.spi.timing().frequency_hz > protocol::SpiTiming::MAX_BURST_FREQUENCY_HZ =>
+            {
+                return false;
+            }
+            protocol::cfg::CFG::BurstEn(v) => new_config.burst_enabled = v,
+            protocol::cfg::CFG::Burst32(v) => new_config.msc_ctrl.burst32 = v,
+            protocol::cfg::CFG::BurstSel(v) => new_config.msc_ctrl.burst_sel = v,
+            protocol::cfg::CFG::LinearAccelerationCompensation(v) => new_config.msc_ctrl.lac = v,
+            protocol::cfg::CFG::PointOfPercussionAlignment(v) => new_config.msc_ctrl.popa = v,
+            protocol::cfg::CFG::SensorBandwidth(v) => new_config.msc_ctrl.bw = v,
+            protocol::cfg::CFG::SyncPolarity(v) => new_config.msc_ctrl.sync_pol = v,
+            protocol::cfg::CFG::DataReadyPolarity(v) => new_config.msc_ctrl.dr_pol = v,
+            protocol::cfg::CFG::SyncMode(v) => new_config.msc_ctrl.sync_mode = v,
+        }
+
+        // msc_ctrl is untouched, there is nothing to write into imu
+        if self.config.msc_ctrl == new_config.msc_ctrl {
+            self.config = new_config;
+            return true;
+        }
+
+        // it has changed something inside msc_ctrl, it has to be written into imu
+        let change =
+            adis::memorymap::to_write(adis::memorymap::MSC_CTRL, new_config.msc_ctrl.into());
+        for d in change {
+            self.spi.transfer(d, clock).ok();
+        }
+
+        // wait for the change to propagate
+        wait_until(clock, clock.now_us() + CONFIG_PROPAGATION_US);
+
+        // check the value inside imu, accept the change only if it is correct
+        let request = adis::memorymap::request(adis::memorymap::MSC_CTRL);
+        return match self.request_response(request, clock) {
+            Ok(r) if new_config.msc_ctrl == r.into() => {
+                self.config = new_config;
+                true
+            }
+            _ => false,
+        };
+    }
+
+    fn reset(&mut self, clock: &dyn Clock) {
+        self.n_rst.set_low().ok();
+        wait_until(clock, clock.now_us() + RESET_PULSE_US);
+        self.n_rst.set_high().ok();
+
+        self.spi.cancel_burst();
+        self.config = Config::default();
+    }
+
+    fn start_burst(&mut self, clock: &dyn Clock) -> Result<usize, BusError> {
+        let len = self.burst_len();
+        self.spi.request_burst(len, clock)?;
+        return Ok(len);
+    }
+
+    fn finish_burst(
+        &mut self,
+        sensor: protocol::SensorIndex,
+        words: &[u16],
+        clock: &dyn Clock,
+    ) -> Result<protocol::Message, BusError> {
+        self.spi.finish_burst(words.len(), clock)?;
+        return self.burst_message(sensor, words);
+    }
+
+    fn burst(
+        &mut self,
+        sensor: protocol::SensorIndex,
+        clock: &dyn Clock,
+    ) -> Result<protocol::Message, BusError> {
+        let len = self.start_burst(clock)?;
+
+        let mut imu_out = [0; BURST32_WORDS];
+        self.spi.read_burst(&mut imu_out[..len], clock)?;
+
+        return self.burst_message(sensor, &imu_out[..len]);
+    }
+
+    fn request_response(&mut self, data: u16, clock: &dyn Clock) -> Result<u16, BusError> {
+        return self.spi.request_response(data, clock);
+    }
+
+    fn spi_timing(&self) -> Option<&protocol::SpiTiming> {
+        return Some(self.spi.timing());
+    }
+
+    fn set_spi_timing(&mut self, timing: protocol::SpiTiming) -> Option<protocol::SpiTiming> {
+        let max_frequency_hz = match self.config.burst_enabled {
+            true => protocol::SpiTiming::MAX_BURST_FREQUENCY_HZ,
+            false => protocol::SpiTiming::MAX_FREQUENCY_HZ,
+        };
+        if timing.frequency_hz > max_frequency_hz {
+            return None;
+        }
+
+        return self.spi.set_timing(timing);
+    }
+}
+
+/// Stands in for the second sensor on boards with only one.
+pub struct NoImu;
+
+impl Imu for NoImu {
+    fn config(&self) -> Option<&Config> {
+        return None;
+    }
+
+    fn configure(&mut self, _cfg: protocol::cfg::CFG, _clock: &dyn Clock) -> bool {
+        return false;
+    }
+
+    fn reset(&mut self, _clock: &dyn Clock) {}
+
+    fn start_burst(&mut self, _clock: &dyn Clock) -> Result<usize, BusError> {
+        return Err(BusError::NoSensor);
+    }
+
+    fn finish_burst(
+        &mut self,
+        _sensor: protocol::SensorIndex,
+        _words: &[u16],
+        _clock: &dyn Clock,
+    ) -> Result<protocol::Message, BusError> {
+        return Err(BusError::NoSensor);
+    }
+
+    fn burst(
+        &mut self,
+        _sensor: protocol::SensorIndex,
+        _clock: &dyn Clock,
+    ) -> Result<protocol::Message, BusError> {
+        return Err(BusError::NoSensor);
+    }
+
+    fn request_response(&mut self, _data: u16, _clock: &dyn Clock) -> Result<u16, BusError> {
+        return Err(BusError::NoSensor);
+    }
+
+    fn spi_timing(&self) -> Option<&protocol::SpiTiming> {
+        return None;
+    }
+
+    fn set_spi_timing(&mut self, _timing: protocol::SpiTiming) -> Option<protocol::SpiTiming> {
+        return None;
+    }
+}
@@ -0,0 +1,235 @@
+use protocol::adis::msc_ctrl::MscCtrl;
+
+use super::Config;
+
+/// Flash sector holding the settings.
+pub const SECTOR_SIZE: usize = 4096;
+/// Smallest piece the flash programs at once, every save takes one.
+pub const SLOT_SIZE: usize = 256;
+const SLOTS: usize = SECTOR_SIZE / SLOT_SIZE;
+
+const MAGIC: [u8; 2] = *b"AS";
+/// Changes together with the record layout, records of other versions are ignored.
+const FORMAT_VERSION: u8 = 1;
+const SENSORS: usize = 2;
+/// Magic, version, flags and msc_ctrl of every sensor, CRC.
+const RECORD_LEN: usize = 2 + 1 + SENSORS * 3 + 4;
+
+const PRESENT: u8 = 1 << 0;
+const BURST_ENABLED: u8 = 1 << 1;
+
+/// Why the settings could not be stored.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FlashError {
+    /// The flash failed to program or erase.
+    Write,
+    /// Settings read back differ from those saved.
+    Verify,
+}
+
+/// Sector of flash the settings are kept in, offsets are relative to its start.
+pub trait SettingsFlash {
+    fn read(&self, offset: usize, buf: &mut [u8]);
+
+    /// Programs erased slot, bits only go from 1 to 0.
+    fn program(&mut self, offset: usize, data: &[u8; SLOT_SIZE]) -> Result<(), FlashError>;
+
+    /// Erases the whole sector, all bytes become 0xFF.
+    fn erase(&mut self) -> Result<(), FlashError>;
+}
+
+/// Configuration of the sensors kept over power cycles, `None` for sensors that were not present.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Default)]
+pub struct Settings {
+    pub sensors: [Option<Config>; SENSORS],
+}
+
+impl Settings {
+    /// Sensors with bursts enabled, the bursts are disabled in the settings, so that they can start later.
+    pub fn take_autostart(&mut self) -> [bool; SENSORS] {
+        let autostart = self.sensors.map(|c| c.is_some_and(|c| c.burst_enabled));
+        self.sensors
+            .iter_mut()
+            .flatten()
+            .for_each(|c| c.burst_enabled = false);
+        return autostart;
+    }
+
+    fn encode(&self) -> [u8; SLOT_SIZE] {
+        let mut slot = [0xFF; SLOT_SIZE];
+        slot[..2].copy_from_slice(&MAGIC);
+        slot[2] = FORMAT_VERSION;
+
+        for (i, config) in self.sensors.iter().enumerate() {
+            let (flags, msc_ctrl) = match config {
+                Some(c) => (
+                    PRESENT | if c.burst_enabled { BURST_ENABLED } else { 0 },
+                    c.msc_ctrl.into(),
+                ),
+                None => (0, 0u16),
+            };
+            slot[3 + 3 * i] = flags;
+            slot[4 + 3 * i..6 + 3 * i].copy_from_slice(&msc_ctrl.to_le_bytes());
+        }
+
+        let crc = crc32(&slot[..RECORD_LEN - 4]);
+        slot[RECORD_LEN - 4..RECORD_LEN].copy_from_slice(&crc.to_le_bytes());
+        return slot;
+    }
+
+    fn decode(record: &[u8; RECORD_LEN]) -> Option<Self> {
+        let crc = u32::from_le_bytes(record[RECORD_LEN - 4..].try_into().ok()?);
+        if record[..2] != MAGIC
+            || record[2] != FORMAT_VERSION
+            || crc != crc32(&record[..RECORD_LEN - 4])
+        {
+            return None;
+        }
+
+        let mut settings = Settings::default();
+        for (i, config) in settings.sensors.iter_mut().enumerate() {
+            let flags = record[3 + 3 * i];
+            let msc_ctrl = u16::from_le_bytes([record[4 + 3 * i], record[5 + 3 * i]]);
+            if flags & PRESENT != 0 {
+                *config = Some(Config {
+                    burst_enabled: flags & BURST_ENABLED != 0,
+                    msc_ctrl: MscCtrl::from(msc_ctrl),
+                });
+            }
+        }
+        return Some(settings);
+    }
+}
+
+/// Settings sector filled slot after slot, erased only when no slot is left, so the flash wears evenly.
+///
+/// The newest valid slot wins, slot broken by power loss while saving is skipped.
+pub struct SettingsStore<F> {
+    flash: F,
+}
+
+impl<F: SettingsFlash> SettingsStore<F> {
+    pub fn new(flash: F) -> Self {
+        return Self { flash };
+    }
+
+    pub fn load(&self) -> Option<Settings> {
+        return (0..self.free_slot().unwrap_or(SLOTS))
+            .rev()
+            .find_map(|slot| {
+                let mut record = [0; RECORD_LEN];
+                self.flash.read(slot * SLOT_SIZE, &mut record);
+                return Settings::decode(&record);
+            });
+    }
+
+    pub fn save(&mut self, settings: &Settings) -> Result<(), FlashError> {
+        let slot = match self.free_slot() {
+            Some(slot) => slot,
+            None => {
+                self.flash.erase()?;
+                0
+            }
+        };
+        self.flash.program(slot * SLOT_SIZE, &settings.encode())?;
+
+        return match self.load() {
+            Some(stored) if stored == *settings => Ok(()),
+            _ => Err(FlashError::Verify),
+        };
+    }
+
+    pub fn erase(&mut self) -> Result<(), FlashError> {
+        return self.flash.erase();
+    }
+
+    /// First slot never programmed since the erase.
+    fn free_slot(&self) -> Option<usize> {
+        return (0..SLOTS).find(|slot| {
+            let mut first = [0];
+            self.flash.read(slot * SLOT_SIZE, &mut first);
+            return first[0] == 0xFF;
+        });
+    }
+}
+
+/// CRC-32 as in Ethernet and zip.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    return !crc;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct MemoryFlash {
+        sector: [u8; SECTOR_SIZE],
+        erases: u32,
+    }
+
+    impl SettingsFlash for &mut MemoryFlash {
+        fn read(&self, offset: usize, buf: &mut [u8]) {
+            buf.copy_from_slice(&self.sector[offset..offset + buf.len()]);
+        }
+
+        fn program(&mut self, offset: usize, data: &[u8; SLOT_SIZE]) -> Result<(), FlashError> {
+            for (byte, d) in self.sector[offset..offset + SLOT_SIZE].iter_mut().zip(data) {
+                *byte &= d;
+            }
+            return Ok(());
+        }
+
+        fn erase(&mut self) -> Result<(), FlashError> {
+            self.sector = [0xFF; SECTOR_SIZE];
+            self.erases += 1;
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn newest_valid_slot_wins() {
+        let mut flash = MemoryFlash {
+            sector: [0xFF; SECTOR_SIZE],
+            erases: 0,
+        };
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+
+        let mut settings = Settings::default();
+        {
+            let mut store = SettingsStore::new(&mut flash);
+            assert_eq!(store.load(), None);
+
+            for i in 0..=SLOTS {
+                settings.sensors[0] = Some(Config {
+                    burst_enabled: i % 2 == 0,
+                    ..Default::default()
+                });
+                store.save(&settings).unwrap();
+                assert_eq!(store.load(), Some(settings));
+            }
+        }
+        assert_eq!(flash.erases, 1);
+
+        // slot left half written by power loss is skipped
+        flash.sector[SLOT_SIZE] = b'A';
+        let mut store = SettingsStore::new(&mut flash);
+        assert_eq!(store.load(), Some(settings));
+
+        store.erase().unwrap();
+        assert_eq!(store.load(), None);
+
+        assert_eq!(settings.take_autostart(), [true, false]);
+        assert!(!settings.sensors[0].unwrap().burst_enabled);
+    }
+}
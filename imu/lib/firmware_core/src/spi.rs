@@ -0,0 +1,294 @@
+use core::convert::Infallible;
+
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+use protocol::adis;
+
+use super::{wait_until, Clock};
+
+/// SPI bus with clock the firmware can change.
+pub trait SpiClock {
+    /// Sets the clock as close to `frequency_hz` as the bus can, returns the frequency achieved.
+    fn set_frequency(&mut self, frequency_hz: u32) -> u32;
+}
+
+/// Why the sensor could not be talked to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusError {
+    /// Requested burst waits to be read, registers can not be accessed.
+    BurstPending,
+    /// Words read do not match the burst requested, the burst is lost.
+    LengthMismatch,
+    /// The SPI peripheral or chip select failed.
+    Spi,
+    /// No sensor at the index.
+    NoSensor,
+}
+
+/// Chip select framed by the SPI peripheral itself, setting it does nothing.
+pub struct HardwareCs;
+
+impl OutputPin for HardwareCs {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        return Ok(());
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        return Ok(());
+    }
+}
+
+/// SPI bus of single ADIS sensor, the firmware talks to the sensor only through it.
+///
+/// Every transfer waits for the stall time since the previous one and is framed by chip select, the burst as
+/// a whole. Burst request has to be followed by reading its words, either by `read_burst` or by other means (DMA)
+/// and `finish_burst`, registers can not be accessed meanwhile.
+///
+/// Interrupts share it through its owner, on the pico the acquisition behind a critical section `Mutex`.
+pub struct AdisSpi<SPI, CS = HardwareCs> {
+    pub(crate) spi: SPI,
+    cs: CS,
+    timing: protocol::SpiTiming,
+    last_comm_us: Option<u64>,
+    /// Words of the burst requested and not read yet.
+    burst_len: Option<usize>,
+}
+
+impl<SPI> AdisSpi<SPI>
+where
+    SPI: Transfer<u16>,
+{
+    pub fn new(spi: SPI) -> Self {
+        return Self::with_cs(spi, HardwareCs);
+    }
+}
+
+impl<SPI, CS> AdisSpi<SPI, CS>
+where
+    SPI: Transfer<u16>,
+    CS: OutputPin,
+{
+    /// Bus with chip select driven as output pin, for peripherals that do not frame it.
+    pub fn with_cs(spi: SPI, mut cs: CS) -> Self {
+        cs.set_high().ok();
+
+        return Self {
+            spi,
+            cs,
+            timing: protocol::SpiTiming::default(),
+            last_comm_us: None,
+            burst_len: None,
+        };
+    }
+
+    pub fn timing(&self) -> &protocol::SpiTiming {
+        return &self.timing;
+    }
+
+    /// Transfers single register word, fails while burst waits to be read.
+    pub fn transfer(&mut self, data: u16, clock: &dyn Clock) -> Result<u16, BusError> {
+        if self.burst_len.is_some() {
+            return Err(BusError::BurstPending);
+        }
+
+        let mut word = [data];
+        self.frame(&mut word, clock)?;
+        return Ok(word[0]);
+    }
+
+    /// Sends register read request, the response comes with the following transfer.
+    pub fn request_response(&mut self, request: u16, clock: &dyn Clock) -> Result<u16, BusError> {
+        self.transfer(request, clock)?;
+        return self.transfer(0, clock);
+    }
+
+    /// Sends burst request, `len` words have to be read afterwards.
+    pub fn request_burst(&mut self, len: usize, clock: &dyn Clock) -> Result<(), BusError> {
+        // burst not read is lost with the new request anyway
+        self.burst_len = None;
+        self.transfer(adis::memorymap::request(adis::memorymap::GLOB_CMD), clock)?;
+        self.burst_len = Some(len);
+        return Ok(());
+    }
+
+    /// Reads words of the requested burst, the burst is gone even if their count does not match.
+    pub fn read_burst(&mut self, words: &mut [u16], clock: &dyn Clock) -> Result<(), BusError> {
+        if self.burst_len.take() != Some(words.len()) {
+            return Err(BusError::LengthMismatch);
+        }
+
+        return self.frame(words, clock);
+    }
+
+    /// Notes that `len` words of the requested burst were read by other means, the stall time starts over.
+    pub fn finish_burst(&mut self, len: usize, clock: &dyn Clock) -> Result<(), BusError> {
+        self.last_comm_us = Some(clock.now_us());
+
+        return match self.burst_len.take() {
+            Some(requested) if requested == len => Ok(()),
+            _ => Err(BusError::LengthMismatch),
+        };
+    }
+
+    /// Forgets requested burst, the sensor was reset.
+    pub fn cancel_burst(&mut self) {
+        self.burst_len = None;
+    }
+
+    fn frame(&mut self, words: &mut [u16], clock: &dyn Clock) -> Result<(), BusError> {
+        if let Some(last) = self.last_comm_us {
+            wait_until(clock, last + self.timing.stall_us as u64);
+        }
+
+        self.cs.set_low().map_err(|_| BusError::Spi)?;
+        let res = self
+            .spi
+            .transfer(words)
+            .map(|_| ())
+            .map_err(|_| BusError::Spi);
+        self.cs.set_high().ok();
+
+        self.last_comm_us = Some(clock.now_us());
+        return res;
+    }
+}
+
+impl<SPI, CS> AdisSpi<SPI, CS>
+where
+    SPI: Transfer<u16> + SpiClock,
+    CS: OutputPin,
+{
+    /// Applies timing within the `SpiTiming` bounds, returns it with the frequency achieved, `None` if it was rejected.
+    pub fn set_timing(&mut self, timing: protocol::SpiTiming) -> Option<protocol::SpiTiming> {
+        if !timing.is_valid() {
+            return None;
+        }
+
+        self.timing = protocol::SpiTiming {
+            frequency_hz: self.spi.set_frequency(timing.frequency_hz),
+            stall_us: timing.stall_us,
+        };
+        return Some(self.timing);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    /// Start time and length of the transfers.
+    type Transfers = Rc<RefCell<Vec<(u64, usize)>>>;
+    /// Whether chip select is high and how many times it went low.
+    type CsState = Rc<Cell<(bool, u32)>>;
+
+    /// Records the transfers, answers every word incremented.
+    struct MockSpi {
+        now_us: Rc<Cell<u64>>,
+        transfers: Transfers,
+    }
+
+    impl Transfer<u16> for MockSpi {
+        type Error = Infallible;
+
+        fn transfer<'w>(&mut self, words: &'w mut [u16]) -> Result<&'w [u16], Infallible> {
+            self.transfers
+                .borrow_mut()
+                .push((self.now_us.get(), words.len()));
+            words.iter_mut().for_each(|w| *w = w.wrapping_add(1));
+            return Ok(words);
+        }
+    }
+
+    /// Counts the falling edges, fails if the bus is selected twice.
+    struct MockCs(CsState);
+
+    impl OutputPin for MockCs {
+        type Error = ();
+
+        fn set_low(&mut self) -> Result<(), ()> {
+            let (high, lows) = self.0.get();
+            self.0.set((false, lows + 1));
+            return if high { Ok(()) } else { Err(()) };
+        }
+
+        fn set_high(&mut self) -> Result<(), ()> {
+            self.0.set((true, self.0.get().1));
+            return Ok(());
+        }
+    }
+
+    struct MockClock(Rc<Cell<u64>>);
+
+    impl Clock for MockClock {
+        fn now_us(&self) -> u64 {
+            self.0.set(self.0.get() + 1);
+            return self.0.get();
+        }
+    }
+
+    fn bus() -> (AdisSpi<MockSpi, MockCs>, MockClock, Transfers, CsState) {
+        let now_us = Rc::new(Cell::new(0));
+        let transfers = Rc::new(RefCell::new(Vec::new()));
+        let cs = Rc::new(Cell::new((false, 0)));
+        let spi = MockSpi {
+            now_us: Rc::clone(&now_us),
+            transfers: Rc::clone(&transfers),
+        };
+
+        let bus = AdisSpi::with_cs(spi, MockCs(Rc::clone(&cs)));
+        return (bus, MockClock(now_us), transfers, cs);
+    }
+
+    #[test]
+    fn transfers_keep_stall_time() {
+        let (mut bus, clock, transfers, cs) = bus();
+        let stall_us = protocol::SpiTiming::default().stall_us as u64;
+
+        assert_eq!(bus.request_response(0x0400, &clock), Ok(1));
+        bus.transfer(0, &clock).unwrap();
+        let transfers = transfers.borrow();
+        assert!(transfers.windows(2).all(|t| t[1].0 - t[0].0 >= stall_us));
+        assert_eq!(cs.get(), (true, 3));
+    }
+
+    #[test]
+    fn burst_blocks_register_access() {
+        let (mut bus, clock, transfers, cs) = bus();
+        let mut words = [0; 10];
+
+        bus.request_burst(words.len(), &clock).unwrap();
+        assert_eq!(bus.transfer(0, &clock), Err(BusError::BurstPending));
+        assert_eq!(
+            bus.read_burst(&mut words[..8], &clock),
+            Err(BusError::LengthMismatch)
+        );
+
+        // burst of wrong length is gone, the next one is requested again
+        assert_eq!(
+            bus.read_burst(&mut words, &clock),
+            Err(BusError::LengthMismatch)
+        );
+        bus.request_burst(words.len(), &clock).unwrap();
+        bus.read_burst(&mut words, &clock).unwrap();
+        assert_eq!(transfers.borrow().last().unwrap().1, words.len());
+        assert_eq!(cs.get(), (true, 3));
+
+        // words read by DMA
+        bus.request_burst(words.len(), &clock).unwrap();
+        assert_eq!(bus.transfer(0, &clock), Err(BusError::BurstPending));
+        let read_at = clock.now_us();
+        bus.finish_burst(words.len(), &clock).unwrap();
+        assert_eq!(
+            bus.finish_burst(words.len(), &clock),
+            Err(BusError::LengthMismatch)
+        );
+
+        bus.transfer(0, &clock).unwrap();
+        let stall_us = protocol::SpiTiming::default().stall_us as u64;
+        assert!(transfers.borrow().last().unwrap().0 >= read_at + stall_us);
+    }
+}
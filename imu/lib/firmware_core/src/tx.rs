@@ -0,0 +1,139 @@
+use super::MAX_FRAME_LEN;
+
+/// Bytes of frames waiting for the USB.
+pub const TX_QUEUE_LEN: usize = 1024;
+
+/// Frames waiting for the USB, which takes them in pieces of any size.
+///
+/// Frames that do not fit are dropped whole and reported by `Overflow` message once there is room again.
+/// `Status` going through gets the count of all frames dropped so far.
+pub struct TxQueue {
+    bytes: heapless::Deque<u8, TX_QUEUE_LEN>,
+    dropped: u32,
+    overflows: u32,
+}
+
+impl TxQueue {
+    pub fn new() -> Self {
+        return Self {
+            bytes: heapless::Deque::new(),
+            dropped: 0,
+            overflows: 0,
+        };
+    }
+
+    /// Queues frame of the message, returns whether it fits.
+    pub fn push(&mut self, message: &protocol::Message) -> bool {
+        if self.dropped > 0
+            && self.push_frame(&protocol::Message::Overflow {
+                source: protocol::OverflowSource::Usb,
+                dropped: self.dropped,
+            })
+        {
+            self.dropped = 0;
+        }
+
+        let mut message = *message;
+        if let protocol::Message::Status(status) = &mut message {
+            status.usb_overflows = self.overflows;
+        }
+
+        let queued = self.push_frame(&message);
+        if !queued {
+            self.dropped = self.dropped.saturating_add(1);
+            self.overflows = self.overflows.wrapping_add(1);
+        }
+        return queued;
+    }
+
+    /// Hands queued bytes to `write` until it takes none, `write` returns how many it took.
+    pub fn write<F: FnMut(&[u8]) -> usize>(&mut self, mut write: F) {
+        while !self.bytes.is_empty() {
+            let taken = write(self.bytes.as_slices().0);
+            if taken == 0 {
+                return;
+            }
+            for _ in 0..taken {
+                self.bytes.pop_front();
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.bytes.is_empty();
+    }
+
+    fn push_frame(&mut self, message: &protocol::Message) -> bool {
+        let Ok(frame) = protocol::to_vec_cobs::<_, MAX_FRAME_LEN>(message) else {
+            return false;
+        };
+        if TX_QUEUE_LEN - self.bytes.len() < frame.len() {
+            return false;
+        }
+
+        for byte in frame {
+            self.bytes.push_back(byte).ok();
+        }
+        return true;
+    }
+}
+
+impl Default for TxQueue {
+    fn default() -> Self {
+        return Self::new();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Decoder;
+
+    #[test]
+    fn overflow_is_reported() {
+        let mut tx = TxQueue::new();
+        let rqr = protocol::Message::RQR(0x7200);
+
+        let mut queued = 0;
+        while tx.push(&rqr) {
+            queued += 1;
+        }
+
+        // the USB takes a few bytes at a time
+        let mut decoder = Decoder::new();
+        let mut received = Vec::new();
+        tx.write(|bytes| {
+            let taken = core::cmp::min(bytes.len(), 7);
+            received.extend(decoder.feed(&bytes[..taken]));
+            return taken;
+        });
+        assert!(tx.is_empty());
+        assert_eq!(received.len(), queued);
+
+        assert!(tx.push(&protocol::Message::RST));
+        assert!(tx.push(&protocol::Message::Status(Default::default())));
+        tx.write(|bytes| {
+            received.extend(decoder.feed(bytes));
+            return bytes.len();
+        });
+        let status = protocol::Status {
+            usb_overflows: 1,
+            ..Default::default()
+        };
+        assert_eq!(
+            received[queued..],
+            [
+                protocol::Message::Overflow {
+                    source: protocol::OverflowSource::Usb,
+                    dropped: 1
+                },
+                protocol::Message::RST,
+                protocol::Message::Status(status)
+            ]
+        );
+
+        tx.push(&rqr);
+        tx.write(|_| 0);
+        assert!(!tx.is_empty());
+    }
+}
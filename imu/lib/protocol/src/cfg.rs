@@ -14,4 +14,5 @@ pub enum CFG {
     SensorBandwidth (SensorBandwidth),
     SyncPolarity (SyncPolarity),
     DataReadyPolarity (DataReadyPolarity),
+    SyncMode (SyncMode),
 }
@@ -6,20 +6,280 @@ pub const DEFAULT_BAUDRATE: u32 = 115200;
 pub mod cfg;
 
 pub use adis;
-use serde::{Deserialize, Serialize};
+use adis::burstmem::BurstMemory;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
 pub use heapless::Vec;
 pub use postcard::accumulator::{CobsAccumulator, FeedResult};
-pub use postcard::{from_bytes_cobs, to_vec_cobs};
 pub use postcard::Error as PostcardError;
+pub use postcard::{from_bytes_cobs, to_vec_cobs};
 
+/// Index of the sensor on the board, boards with two sensors use 0 and 1.
+pub type SensorIndex = u8;
 
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 pub enum Message {
-    CFG(cfg::CFG),
+    CFG(SensorIndex, cfg::CFG),
+    /// Register request and response, always the sensor 0.
     RQR(u16),
-    B16(cfg::BurstSel, adis::burstmem::BurstMemory16),
-    B32(cfg::BurstSel, adis::burstmem::BurstMemory32),
+    B16(SensorIndex, cfg::BurstSel, adis::burstmem::BurstMemory16),
+    B32(SensorIndex, cfg::BurstSel, adis::burstmem::BurstMemory32),
+    /// Resets all sensors.
     RST,
     ERR(u8),
+    /// Consecutive bursts of one sensor sent together.
+    BAT(Batch),
+    /// Messages the board dropped at `source` since the previous report from there, because they were not taken in time.
+    Overflow {
+        source: OverflowSource,
+        dropped: u32,
+    },
+    /// Configuration stored in the board, acknowledged by the same message when it succeeds.
+    STO(Store),
+    /// Board supervising the sensor found out something about it.
+    Health(SensorIndex, Health),
+    /// Asks the board for `Status` right away.
+    StatusRequest,
+    /// State of the board, sent periodically as heartbeat and on `StatusRequest`.
+    Status(Status),
+    /// Reboots the board into the ROM USB bootloader for firmware update, acknowledged by the same message first.
+    EnterBootloader,
+    /// SPI timing of the sensor, acknowledged with the timing applied, the frequency as the board achieved it.
+    /// Rejected timing is answered with the one still in use.
+    SpiTiming(SensorIndex, SpiTiming),
+}
+
+/// Where the board dropped messages.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverflowSource {
+    /// Queue between the core reading the sensors and the core serving the USB.
+    Queue,
+    /// Buffer of the USB serial, the host did not read in time.
+    Usb,
+}
+
+/// What to do with the configuration stored in the board, the stored one is applied at power up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Store {
+    /// Stores current configuration of all sensors.
+    Save,
+    /// Applies the stored configuration now.
+    Load,
+    /// Forgets the stored configuration, the board starts with the default one.
+    Erase,
+}
+
+/// What the board found out about a sensor with bursts enabled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Health {
+    /// Data ready stopped toggling, the sensor is being reset.
+    DataReadyTimeout,
+    /// Sensor returns only zeros, it is being reset.
+    ZeroBursts,
+    /// Sensor sends good bursts again after being reset.
+    Recovered,
+    /// Resets did not help, the board does not touch the sensor any more.
+    Failed,
+}
+
+/// Board state, the counters run since power up and wrap around.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct Status {
+    /// Time since power up in milliseconds.
+    pub uptime_ms: u32,
+    /// `None` for sensor the board does not have.
+    pub sensors: [Option<SensorStatus>; 2],
+    /// Bursts read from the sensors.
+    pub bursts: u32,
+    /// Bursts and responses dropped before they got to the USB side.
+    pub dropped: u32,
+    /// Transfers with the sensors that failed.
+    pub spi_errors: u32,
+    /// Frames dropped because the host did not take them from the USB in time.
+    pub usb_overflows: u32,
+    /// Stored configuration was not accepted by the sensors, they may run with the default one.
+    pub settings_failed: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub struct SensorStatus {
+    pub burst_enabled: bool,
+    pub msc_ctrl: cfg::MscCtrl,
+    /// DIAG_STAT of the last burst read from the sensor.
+    pub diag_stat: u16,
+}
+
+/// SPI clock and stall time between transfers of a sensor, the board keeps it over `RST`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SpiTiming {
+    pub frequency_hz: u32,
+    pub stall_us: u16,
+}
+
+impl SpiTiming {
+    pub const MIN_FREQUENCY_HZ: u32 = 100_000;
+    /// Register reads allow up to 2 MHz.
+    pub const MAX_FREQUENCY_HZ: u32 = 2_000_000;
+    /// Burst reads allow only up to 1 MHz, faster clock is rejected while bursts are enabled.
+    pub const MAX_BURST_FREQUENCY_HZ: u32 = 1_000_000;
+    /// Shortest stall time the sensors allow.
+    pub const MIN_STALL_US: u16 = 16;
+    pub const MAX_STALL_US: u16 = 1_000;
+
+    /// Whether the timing is within the bounds, bursts aside.
+    pub fn is_valid(&self) -> bool {
+        return (Self::MIN_FREQUENCY_HZ..=Self::MAX_FREQUENCY_HZ).contains(&self.frequency_hz)
+            && (Self::MIN_STALL_US..=Self::MAX_STALL_US).contains(&self.stall_us);
+    }
+}
+
+impl Default for SpiTiming {
+    fn default() -> Self {
+        return Self {
+            frequency_hz: 950_000,
+            stall_us: Self::MIN_STALL_US,
+        };
+    }
+}
+
+/// Bursts in a batch at most, the firmware sends fewer if the frame would get too long.
+pub const MAX_BATCH: usize = 4;
+
+/// Consecutive bursts of one sensor in one frame, saves USB transfers at high data rates.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Batch {
+    pub sensor: SensorIndex,
+    pub burst_sel: cfg::BurstSel,
+    /// Data counter of the first burst, the others follow it without a gap.
+    pub first_cntr: u16,
+    /// Board time of the first burst in microseconds, wraps around.
+    pub timestamp_us: u32,
+    bursts: BatchBursts,
+}
+
+impl Batch {
+    /// Batch starting with the burst message, `None` for other messages.
+    pub fn new(message: Message, timestamp_us: u32) -> Option<Self> {
+        let (sensor, burst_sel, first_cntr, bursts) = match message {
+            Message::B16(sensor, sel, burst) => (
+                sensor,
+                sel,
+                burst.data_cntr(),
+                BatchBursts::B16(Bursts::new(burst)),
+            ),
+            Message::B32(sensor, sel, burst) => (
+                sensor,
+                sel,
+                burst.data_cntr(),
+                BatchBursts::B32(Bursts::new(burst)),
+            ),
+            _ => return None,
+        };
+
+        return Some(Self {
+            sensor,
+            burst_sel,
+            first_cntr,
+            timestamp_us,
+            bursts,
+        });
+    }
+
+    /// Adds burst message at the end, `false` if the batch is full
+    /// or the burst does not follow the last one of the same sensor and kind.
+    pub fn push(&mut self, message: Message) -> bool {
+        let expected = self.first_cntr.wrapping_add(self.len() as u16);
+        let follows =
+            |sensor, sel, cntr| sensor == self.sensor && sel == self.burst_sel && cntr == expected;
+
+        return match (&mut self.bursts, message) {
+            (BatchBursts::B16(bursts), Message::B16(sensor, sel, burst))
+                if follows(sensor, sel, burst.data_cntr()) =>
+            {
+                bursts.push(burst)
+            }
+            (BatchBursts::B32(bursts), Message::B32(sensor, sel, burst))
+                if follows(sensor, sel, burst.data_cntr()) =>
+            {
+                bursts.push(burst)
+            }
+            _ => false,
+        };
+    }
+
+    pub fn len(&self) -> usize {
+        return match &self.bursts {
+            BatchBursts::B16(bursts) => bursts.as_slice().len(),
+            BatchBursts::B32(bursts) => bursts.as_slice().len(),
+        };
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    /// The bursts as if they were sent one by one.
+    pub fn messages(&self) -> impl Iterator<Item = Message> + '_ {
+        return (0..self.len()).map(move |i| match &self.bursts {
+            BatchBursts::B16(bursts) => Message::B16(self.sensor, self.burst_sel, bursts.data[i]),
+            BatchBursts::B32(bursts) => Message::B32(self.sensor, self.burst_sel, bursts.data[i]),
+        });
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+enum BatchBursts {
+    B16(Bursts<adis::burstmem::BurstMemory16>),
+    B32(Bursts<adis::burstmem::BurstMemory32>),
+}
+
+/// Only the used bursts go over the wire.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Bursts<B> {
+    len: u8,
+    data: [B; MAX_BATCH],
+}
+
+impl<B: Copy + Default> Bursts<B> {
+    fn new(first: B) -> Self {
+        let mut data = [B::default(); MAX_BATCH];
+        data[0] = first;
+        return Self { len: 1, data };
+    }
+
+    fn push(&mut self, burst: B) -> bool {
+        let len = self.len as usize;
+        if len >= MAX_BATCH {
+            return false;
+        }
+
+        self.data[len] = burst;
+        self.len += 1;
+        return true;
+    }
+}
+
+impl<B> Bursts<B> {
+    fn as_slice(&self) -> &[B] {
+        return &self.data[..core::cmp::min(self.len as usize, MAX_BATCH)];
+    }
+}
+
+impl<B: Serialize> Serialize for Bursts<B> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        return self.as_slice().serialize(serializer);
+    }
+}
+
+impl<'de, B: Deserialize<'de> + Copy + Default> Deserialize<'de> for Bursts<B> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bursts = Vec::<B, MAX_BATCH>::deserialize(deserializer)?;
+
+        let mut data = [B::default(); MAX_BATCH];
+        data[..bursts.len()].copy_from_slice(&bursts);
+        return Ok(Self {
+            len: bursts.len() as u8,
+            data,
+        });
+    }
 }
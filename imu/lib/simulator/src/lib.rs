@@ -0,0 +1,429 @@
+//! Simulated ADIS16505 speaking the SPI register protocol.
+//!
+//! Every transferred word is handled as by the real sensor: requests are answered on the following word,
+//! writes go byte by byte (see `adis::memorymap::to_write`) and a request of `GLOB_CMD` starts a burst.
+//! Scaling and 32-bit word layout mirror the decoding in `adis::BurstData`, so values put into
+//! [`Motion`] are what the host reads back.
+
+pub mod motion;
+
+pub use motion::{Motion, Rng, Signal};
+
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use adis::memorymap::*;
+use adis::msc_ctrl::{Burst32, BurstSel, MscCtrl};
+use adis::version::AdisVersion;
+use embedded_hal::blocking::spi::Transfer;
+use embedded_hal::digital::v2::OutputPin;
+
+/// Internal sample rate of the sensor, the output rate is this divided by `DEC_RATE + 1`.
+pub const BASE_SAMPLE_RATE_HZ: f64 = 2000.0;
+
+const REGISTER_COUNT: usize = 0x40;
+const SOFTWARE_RESET: u16 = 1 << 7;
+
+// (OUT, LOW) register pairs
+const GYRO: [(u8, u8); 3] = [(X_GYRO_OUT, X_GYRO_LOW), (Y_GYRO_OUT, Y_GYRO_LOW), (Z_GYRO_OUT, Z_GYRO_LOW)];
+const ACCL: [(u8, u8); 3] = [(X_ACCL_OUT, X_ACCL_LOW), (Y_ACCL_OUT, Y_ACCL_LOW), (Z_ACCL_OUT, Z_ACCL_LOW)];
+const DELTANG: [(u8, u8); 3] = [
+    (X_DELTANG_OUT, X_DELTANG_LOW),
+    (Y_DELTANG_OUT, Y_DELTANG_LOW),
+    (Z_DELTANG_OUT, Z_DELTANG_LOW),
+];
+const DELTVEL: [(u8, u8); 3] = [
+    (X_DELTVEL_OUT, X_DELTVEL_LOW),
+    (Y_DELTVEL_OUT, Y_DELTVEL_LOW),
+    (Z_DELTVEL_OUT, Z_DELTVEL_LOW),
+];
+
+/// Handle to a simulated sensor, clones share the same sensor.
+#[derive(Debug, Clone)]
+pub struct AdisSim {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Debug)]
+struct State {
+    version: AdisVersion,
+    registers: [u16; REGISTER_COUNT],
+    output: u16,
+    burst: Option<(Vec<u16>, usize)>,
+    motion: Motion,
+    rng: Rng,
+    time: f64,
+    diag_stat: u16,
+    bad_checksums: usize,
+}
+
+impl AdisSim {
+    pub fn new(version: AdisVersion) -> Self {
+        let mut state = State {
+            version,
+            registers: [0; REGISTER_COUNT],
+            output: 0,
+            burst: None,
+            motion: Motion::default(),
+            rng: Rng::new(0x5EED),
+            time: 0.0,
+            diag_stat: 0,
+            bad_checksums: 0,
+        };
+        state.reset();
+
+        return Self {
+            state: Arc::new(Mutex::new(state)),
+        };
+    }
+
+    pub fn with_motion(self, motion: Motion) -> Self {
+        self.set_motion(motion);
+        return self;
+    }
+
+    pub fn set_motion(&self, motion: Motion) {
+        self.state().motion = motion;
+    }
+
+    pub fn set_seed(&self, seed: u64) {
+        self.state().rng = Rng::new(seed);
+    }
+
+    /// Produces a new sample, as if data ready went active.
+    pub fn tick(&self) {
+        self.state().sample();
+    }
+
+    /// Time between two samples in seconds, given by `DEC_RATE`.
+    pub fn sample_period(&self) -> f64 {
+        return self.state().sample_period();
+    }
+
+    /// Simulated time of the latest sample in seconds.
+    pub fn time(&self) -> f64 {
+        return self.state().time;
+    }
+
+    /// Bits reported in `DIAG_STAT` until set otherwise.
+    pub fn set_diag_stat(&self, diag_stat: u16) {
+        self.state().diag_stat = diag_stat;
+    }
+
+    /// Next `count` bursts will have a wrong checksum.
+    pub fn corrupt_checksums(&self, count: usize) {
+        self.state().bad_checksums = count;
+    }
+
+    /// Restores registers to their power-on values.
+    pub fn reset(&self) {
+        self.state().reset();
+    }
+
+    /// Current value of a register, without the side effects of reading it over SPI.
+    pub fn register(&self, address: u8) -> u16 {
+        return self.state().read(address);
+    }
+
+    /// Pin driving the `RST` line of the sensor, it is reset on rising edge.
+    pub fn reset_pin(&self) -> ResetPin {
+        return ResetPin {
+            sim: self.clone(),
+            low: false,
+        };
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        return self.state.lock().unwrap_or_else(|e| e.into_inner());
+    }
+}
+
+impl Transfer<u16> for AdisSim {
+    type Error = Infallible;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u16]) -> Result<&'w [u16], Infallible> {
+        let mut state = self.state();
+        for w in words.iter_mut() {
+            *w = state.exchange(*w);
+        }
+        return Ok(words);
+    }
+}
+
+impl State {
+    fn reset(&mut self) {
+        self.registers = [0; REGISTER_COUNT];
+        self.set(MSC_CTRL, MscCtrl::default().into());
+        self.set(UP_SCALE, 0x07D0);
+        self.set(FIRM_REV, 0x0104);
+        self.set(FIRM_DM, 0x0619);
+        self.set(FIRM_Y, 0x2020);
+        self.set(SERIAL_NUM, 0x0042);
+
+        let (prod_id, rang_mdl) = match self.version {
+            AdisVersion::ADIS16505_1BMLZ => (16505, 0x0003),
+            AdisVersion::ADIS16505_2BMLZ => (16505, 0x0007),
+            AdisVersion::ADIS16505_3BMLZ => (16505, 0x000F),
+            AdisVersion::ADIS16465_1BMLZ => (16465, 0x0003),
+            AdisVersion::ADIS16465_2BMLZ => (16465, 0x0007),
+            AdisVersion::ADIS16465_3BMLZ => (16465, 0x000F),
+        };
+        self.set(PROD_ID, prod_id);
+        self.set(RANG_MDL, rang_mdl);
+
+        self.output = 0;
+        self.burst = None;
+    }
+
+    fn get(&self, address: u8) -> u16 {
+        return self.registers[(address as usize / 2) % REGISTER_COUNT];
+    }
+
+    fn set(&mut self, address: u8, value: u16) {
+        self.registers[(address as usize / 2) % REGISTER_COUNT] = value;
+    }
+
+    fn read(&self, address: u8) -> u16 {
+        return match address & !1 {
+            DIAG_STAT => self.get(DIAG_STAT) | self.diag_stat,
+            a => self.get(a),
+        };
+    }
+
+    fn write(&mut self, address: u8, byte: u8) {
+        let writable = matches!(
+            address & !1,
+            XG_BIAS_LOW..=ZA_BIAS_HIGH | FILT_CTRL | MSC_CTRL | UP_SCALE | DEC_RATE | GLOB_CMD | USER_SCR1..=USER_SCR3
+        );
+        if !writable {
+            return;
+        }
+
+        let old = self.get(address & !1);
+        let new = if address & 1 == 0 {
+            (old & 0xFF00) | byte as u16
+        } else {
+            (old & 0x00FF) | (byte as u16) << 8
+        };
+
+        if address == GLOB_CMD && new & SOFTWARE_RESET != 0 {
+            self.reset();
+        } else {
+            self.set(address & !1, new);
+        }
+    }
+
+    /// Full duplex exchange of one word, returns the word clocked out of the sensor.
+    fn exchange(&mut self, input: u16) -> u16 {
+        if let Some((words, index)) = &mut self.burst {
+            let output = words[*index];
+            *index += 1;
+            if *index == words.len() {
+                self.burst = None;
+            }
+            return output;
+        }
+
+        let output = self.output;
+        let address = ((input >> 8) & 0x7F) as u8;
+        self.output = 0;
+
+        if input & 0x8000 != 0 {
+            self.write(address, input as u8);
+        } else if address == GLOB_CMD {
+            self.burst = Some((self.burst_words(), 0));
+        } else {
+            self.output = self.read(address);
+        }
+
+        return output;
+    }
+
+    fn burst_words(&mut self) -> Vec<u16> {
+        let msc_ctrl: MscCtrl = self.get(MSC_CTRL).into();
+        let fields = match msc_ctrl.burst_sel {
+            BurstSel::Sel0 => GYRO.iter().chain(ACCL.iter()),
+            BurstSel::Sel1 => DELTANG.iter().chain(DELTVEL.iter()),
+        };
+
+        let mut words = vec![self.read(DIAG_STAT)];
+        for (out, low) in fields {
+            words.push(self.get(*out));
+            if msc_ctrl.burst32 == Burst32::Enabled {
+                words.push(self.get(*low));
+            }
+        }
+        words.push(self.get(TEMP_OUT));
+        words.push(self.get(DATA_CNTR));
+
+        let mut checksum = words
+            .iter()
+            .fold(0_u16, |sum, w| sum.wrapping_add((w & 0xFF) + (w >> 8)));
+        if self.bad_checksums > 0 {
+            self.bad_checksums -= 1;
+            checksum = !checksum;
+        }
+        words.push(checksum);
+
+        return words;
+    }
+
+    fn sample_period(&self) -> f64 {
+        return (self.get(DEC_RATE) as f64 + 1.0) / BASE_SAMPLE_RATE_HZ;
+    }
+
+    fn sample(&mut self) {
+        let period = self.sample_period();
+        self.time += period;
+
+        for i in 0..3 {
+            let rate = self.motion.gyro[i].value(self.time, &mut self.rng);
+            let accl = self.motion.accl[i].value(self.time, &mut self.rng);
+
+            self.set_split(GYRO[i], rate / self.version.gyro_constant());
+            self.set_split(ACCL[i], accl / self.version.accl_constant());
+            self.set_split(DELTANG[i], rate * period / self.version.deltang_constant());
+            self.set_split(DELTVEL[i], accl * period / self.version.deltvel_constant());
+        }
+
+        let temp = self.motion.temp.value(self.time, &mut self.rng);
+        self.set(TEMP_OUT, to_word(temp / self.version.temp_constant()));
+        self.set(DATA_CNTR, self.get(DATA_CNTR).wrapping_add(1));
+    }
+
+    /// Integer part goes to OUT, fraction scaled by 2^15 goes to LOW.
+    fn set_split(&mut self, (out, low): (u8, u8), raw: f64) {
+        let raw = raw.clamp(i16::MIN as f64, i16::MAX as f64);
+        let integer = raw.floor();
+        self.set(out, integer as i16 as u16);
+        self.set(low, ((raw - integer) * (1 << 15) as f64) as u16);
+    }
+}
+
+fn to_word(raw: f64) -> u16 {
+    return raw.round().clamp(i16::MIN as f64, i16::MAX as f64) as i16 as u16;
+}
+
+/// Simulated bus runs at any clock.
+impl firmware_core::SpiClock for AdisSim {
+    fn set_frequency(&mut self, frequency_hz: u32) -> u32 {
+        return frequency_hz;
+    }
+}
+
+/// Output pin wired to the `RST` line of the simulated sensor.
+#[derive(Debug, Clone)]
+pub struct ResetPin {
+    sim: AdisSim,
+    low: bool,
+}
+
+impl OutputPin for ResetPin {
+    type Error = Infallible;
+
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.low = true;
+        return Ok(());
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        if self.low {
+            self.sim.reset();
+        }
+        self.low = false;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use adis::burstmem::BurstMemory;
+    use adis::{degree_per_second, meter_per_second_squared, BurstData, Sel};
+    use firmware_core::protocol;
+    use std::cell::Cell;
+
+    struct StepClock(Cell<u64>);
+
+    impl firmware_core::Clock for StepClock {
+        fn now_us(&self) -> u64 {
+            self.0.set(self.0.get() + 1);
+            return self.0.get();
+        }
+    }
+
+    type Firmware = firmware_core::Firmware<firmware_core::Sensor<AdisSim, ResetPin>, firmware_core::NoImu, StepClock>;
+
+    fn firmware(sim: &AdisSim) -> Firmware {
+        return Firmware::new(sim.clone(), sim.reset_pin(), StepClock(Cell::new(0)));
+    }
+
+    #[test]
+    fn register_write_and_read() {
+        let mut sim = AdisSim::new(AdisVersion::ADIS16505_2BMLZ);
+
+        let mut words = to_write(DEC_RATE, 0x0123);
+        sim.transfer(&mut words).unwrap();
+        assert_eq!(sim.register(DEC_RATE), 0x0123);
+
+        let mut words = [request(PROD_ID), request(RANG_MDL), request(DEC_RATE), 0];
+        sim.transfer(&mut words).unwrap();
+        assert_eq!(words, [0, 16505, 0x0007, 0x0123]);
+
+        sim.transfer(&mut to_write(GLOB_CMD, SOFTWARE_RESET)).unwrap();
+        assert_eq!(sim.register(DEC_RATE), 0);
+    }
+
+    #[test]
+    fn burst_through_firmware() {
+        let version = AdisVersion::ADIS16505_1BMLZ;
+        let mut motion = Motion::default();
+        motion.gyro[0] = Signal::constant(10.0);
+        let sim = AdisSim::new(version).with_motion(motion);
+        let mut fw = firmware(&sim);
+
+        sim.tick();
+        sim.tick();
+        let burst = match fw.burst(0) {
+            Ok(protocol::Message::B16(0, _, burst)) => burst,
+            m => panic!("unexpected burst {:?}", m),
+        };
+        assert!(!burst.is_corrupted());
+        assert_eq!(burst.data_cntr(), 2);
+
+        let data = BurstData::as_sel0(&burst, &version);
+        match data.data {
+            Sel::Sel0 { x_gyro, z_accl, .. } => {
+                assert!((x_gyro.get::<degree_per_second>() - 10.0).abs() < version.gyro_constant());
+                assert!((z_accl.get::<meter_per_second_squared>() - motion::STANDARD_GRAVITY).abs() < version.accl_constant());
+            }
+            _ => panic!("expected sel0"),
+        }
+    }
+
+    #[test]
+    fn injected_faults() {
+        let sim = AdisSim::new(AdisVersion::ADIS16505_1BMLZ);
+        let mut fw = firmware(&sim);
+        let cfg = protocol::Message::CFG(0, protocol::cfg::CFG::Burst32(Burst32::Enabled));
+        assert_eq!(fw.handle(cfg), Some(cfg));
+
+        sim.set_diag_stat(1 << 8);
+        sim.corrupt_checksums(1);
+        sim.tick();
+
+        let burst = match fw.burst(0) {
+            Ok(protocol::Message::B32(0, _, burst)) => burst,
+            m => panic!("unexpected burst {:?}", m),
+        };
+        assert!(burst.is_corrupted());
+        assert!(adis::diagstat::DiagStat::from(burst.diag_stat()).gyro1_fail);
+
+        fw.handle(protocol::Message::RST);
+        sim.tick();
+        match fw.burst(0) {
+            Ok(protocol::Message::B16(0, _, burst)) => assert!(!burst.is_corrupted()),
+            m => panic!("unexpected burst {:?}", m),
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::f64::consts::PI;
+
+pub const STANDARD_GRAVITY: f64 = 9.80665;
+
+/// Synthetic signal of one axis, `bias + amplitude * sin(2 pi frequency t + phase) + noise`.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Signal {
+    pub bias: f64,
+    pub amplitude: f64,
+    pub frequency: f64,
+    pub phase: f64,
+    /// standard deviation of gaussian white noise
+    pub noise: f64,
+}
+
+impl Signal {
+    pub fn constant(value: f64) -> Self {
+        return Self {
+            bias: value,
+            ..Default::default()
+        };
+    }
+
+    pub fn sine(amplitude: f64, frequency: f64) -> Self {
+        return Self {
+            amplitude,
+            frequency,
+            ..Default::default()
+        };
+    }
+
+    pub fn with_bias(self, bias: f64) -> Self {
+        return Self { bias, ..self };
+    }
+
+    pub fn with_phase(self, phase: f64) -> Self {
+        return Self { phase, ..self };
+    }
+
+    pub fn with_noise(self, noise: f64) -> Self {
+        return Self { noise, ..self };
+    }
+
+    pub fn value(&self, time: f64, rng: &mut Rng) -> f64 {
+        let mut value = self.bias + self.amplitude * (2.0 * PI * self.frequency * time + self.phase).sin();
+        if self.noise != 0.0 {
+            value += self.noise * rng.gaussian();
+        }
+        return value;
+    }
+}
+
+/// Motion of the simulated sensor, rates in deg/s, accelerations in m/s^2 and temperature in deg C.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Motion {
+    pub gyro: [Signal; 3],
+    pub accl: [Signal; 3],
+    pub temp: Signal,
+}
+
+impl Default for Motion {
+    /// Sensor lying still on a table.
+    fn default() -> Self {
+        return Self {
+            gyro: [Signal::default(); 3],
+            accl: [
+                Signal::default(),
+                Signal::default(),
+                Signal::constant(STANDARD_GRAVITY),
+            ],
+            temp: Signal::constant(25.0),
+        };
+    }
+}
+
+/// Small deterministic generator (xorshift64*), so simulated runs are reproducible.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        return Self(seed.max(1));
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        return self.0.wrapping_mul(0x2545_F491_4F6C_DD1D);
+    }
+
+    /// uniform in (0, 1]
+    pub fn uniform(&mut self) -> f64 {
+        return ((self.next_u64() >> 11) + 1) as f64 / (1_u64 << 53) as f64;
+    }
+
+    /// standard normal distribution (Box-Muller)
+    pub fn gaussian(&mut self) -> f64 {
+        return (-2.0 * self.uniform().ln()).sqrt() * (2.0 * PI * self.uniform()).cos();
+    }
+}
@@ -0,0 +1,215 @@
+//! Burst words read by DMA, the CPU only starts the transfer and collects the words afterwards.
+
+use core::marker::PhantomData;
+
+use hal::dma::{bidirectional, Channel, ReadTarget, SingleChannel, WriteTarget, CH0, CH1};
+use hal::pac;
+use rp_pico::hal;
+
+use firmware_core::protocol::SensorIndex;
+use firmware_core::BURST32_WORDS;
+
+/// Offset of the data register in the SPI register block.
+const SSPDR_OFFSET: u32 = 0x008;
+
+/// Value clocked out while the burst words are read.
+static ZERO: u16 = 0;
+
+/// SPI peripheral the DMA can be paced by, DREQ numbers are from the RP2040 datasheet.
+pub trait SpiDreq {
+    const TX: u8;
+    const RX: u8;
+
+    fn data_register() -> u32;
+}
+
+impl SpiDreq for pac::SPI0 {
+    const TX: u8 = 16;
+    const RX: u8 = 17;
+
+    fn data_register() -> u32 {
+        return pac::SPI0::ptr() as u32 + SSPDR_OFFSET;
+    }
+}
+
+impl SpiDreq for pac::SPI1 {
+    const TX: u8 = 18;
+    const RX: u8 = 19;
+
+    fn data_register() -> u32 {
+        return pac::SPI1::ptr() as u32 + SSPDR_OFFSET;
+    }
+}
+
+/// Data register of SPI owned by the sensor, DMA only uses it while the firmware does not.
+pub struct SpiFifo<D>(PhantomData<D>);
+
+unsafe impl<D: SpiDreq> ReadTarget for SpiFifo<D> {
+    type ReceivedWord = u16;
+
+    fn rx_treq() -> Option<u8> {
+        return Some(D::RX);
+    }
+
+    fn rx_address_count(&self) -> (u32, u32) {
+        return (D::data_register(), u32::MAX);
+    }
+
+    fn rx_increment(&self) -> bool {
+        return false;
+    }
+}
+
+unsafe impl<D: SpiDreq> WriteTarget for SpiFifo<D> {
+    type TransmittedWord = u16;
+
+    fn tx_treq() -> Option<u8> {
+        return Some(D::TX);
+    }
+
+    fn tx_address_count(&mut self) -> (u32, u32) {
+        return (D::data_register(), u32::MAX);
+    }
+
+    fn tx_increment(&self) -> bool {
+        return false;
+    }
+}
+
+/// Zero word repeated as many times as there are words to read.
+pub struct Zeros(usize);
+
+unsafe impl ReadTarget for Zeros {
+    type ReceivedWord = u16;
+
+    fn rx_treq() -> Option<u8> {
+        return None;
+    }
+
+    fn rx_address_count(&self) -> (u32, u32) {
+        return (&ZERO as *const u16 as u32, self.0 as u32);
+    }
+
+    fn rx_increment(&self) -> bool {
+        return false;
+    }
+}
+
+/// Buffer for the burst words, only `len` of them are read.
+pub struct Words {
+    buf: &'static mut [u16; BURST32_WORDS],
+    len: usize,
+}
+
+unsafe impl WriteTarget for Words {
+    type TransmittedWord = u16;
+
+    fn tx_treq() -> Option<u8> {
+        return None;
+    }
+
+    fn tx_address_count(&mut self) -> (u32, u32) {
+        return (self.buf.as_mut_ptr() as u32, self.len as u32);
+    }
+
+    fn tx_increment(&self) -> bool {
+        return true;
+    }
+}
+
+type Channels = (Channel<CH0>, Channel<CH1>);
+type Running<D> = bidirectional::Transfer<Channel<CH0>, Channel<CH1>, Zeros, SpiFifo<D>, Words>;
+
+enum State {
+    Idle(Channels, Words),
+    /// Sensor 0 sits on SPI1.
+    Sensor0(Running<pac::SPI1>),
+    /// Sensor 1 sits on SPI0.
+    #[cfg(feature = "second-sensor")]
+    Sensor1(Running<pac::SPI0>),
+    /// Only while switching between the others.
+    Taken,
+}
+
+/// Reads burst words of one sensor at a time, raises DMA_IRQ_0 when they are in.
+pub struct BurstDma {
+    state: State,
+}
+
+impl BurstDma {
+    pub fn new(mut channels: Channels, buf: &'static mut [u16; BURST32_WORDS]) -> Self {
+        channels.1.listen_irq0();
+
+        return Self {
+            state: State::Idle(channels, Words { buf, len: 0 }),
+        };
+    }
+
+    pub fn is_idle(&self) -> bool {
+        return matches!(self.state, State::Idle(..));
+    }
+
+    /// Reads `len` words from the SPI of the sensor, the burst must have been requested already.
+    pub fn start(&mut self, sensor: SensorIndex, len: usize) -> Result<(), ()> {
+        let (channels, mut words) = match core::mem::replace(&mut self.state, State::Taken) {
+            State::Idle(channels, words) => (channels, words),
+            running => {
+                self.state = running;
+                return Err(());
+            }
+        };
+        words.len = core::cmp::min(len, BURST32_WORDS);
+
+        self.state = match sensor {
+            0 => State::Sensor0(
+                bidirectional::Config::new(channels, Zeros(words.len), SpiFifo(PhantomData), words)
+                    .start(),
+            ),
+            #[cfg(feature = "second-sensor")]
+            1 => State::Sensor1(
+                bidirectional::Config::new(channels, Zeros(words.len), SpiFifo(PhantomData), words)
+                    .start(),
+            ),
+            _ => {
+                self.state = State::Idle(channels, words);
+                return Err(());
+            }
+        };
+
+        return Ok(());
+    }
+
+    /// Collects finished transfer, returns the sensor and the words read from it.
+    pub fn finish(&mut self) -> Option<(SensorIndex, &[u16])> {
+        let done = match &self.state {
+            State::Sensor0(transfer) => transfer.is_done(),
+            #[cfg(feature = "second-sensor")]
+            State::Sensor1(transfer) => transfer.is_done(),
+            _ => false,
+        };
+        if !done {
+            return None;
+        }
+
+        let (sensor, mut channels, words) = match core::mem::replace(&mut self.state, State::Taken)
+        {
+            State::Sensor0(transfer) => {
+                let (channels, _, _, words) = transfer.wait();
+                (0, channels, words)
+            }
+            #[cfg(feature = "second-sensor")]
+            State::Sensor1(transfer) => {
+                let (channels, _, _, words) = transfer.wait();
+                (1, channels, words)
+            }
+            _ => unreachable!(),
+        };
+        channels.1.check_irq0();
+        self.state = State::Idle(channels, words);
+
+        return match &self.state {
+            State::Idle(_, words) => Some((sensor, &words.buf[..words.len])),
+            _ => None,
+        };
+    }
+}
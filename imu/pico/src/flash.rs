@@ -0,0 +1,69 @@
+//! Settings sector at the end of the flash.
+//!
+//! The flash can not be read while it is erased or programmed, so the core not doing it waits in RAM.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use firmware_core::settings::{FlashError, SettingsFlash, SECTOR_SIZE, SLOT_SIZE};
+
+/// Flash chip on the pico board.
+const FLASH_SIZE: u32 = 2 * 1024 * 1024;
+const XIP_BASE: u32 = 0x1000_0000;
+const SETTINGS_OFFSET: u32 = FLASH_SIZE - SECTOR_SIZE as u32;
+
+static PARK_REQUEST: AtomicBool = AtomicBool::new(false);
+static PARKED: AtomicBool = AtomicBool::new(false);
+
+/// Settings sector, used only on core1 once core0 runs its loop.
+pub struct PicoFlash;
+
+impl SettingsFlash for PicoFlash {
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        let src = (XIP_BASE + SETTINGS_OFFSET + offset as u32) as *const u8;
+        for (i, byte) in buf.iter_mut().enumerate() {
+            *byte = unsafe { core::ptr::read_volatile(src.add(i)) };
+        }
+    }
+
+    fn program(&mut self, offset: usize, data: &[u8; SLOT_SIZE]) -> Result<(), FlashError> {
+        with_core0_parked(|| unsafe {
+            rp2040_flash::flash::flash_range_program(SETTINGS_OFFSET + offset as u32, data, true);
+        });
+        return Ok(());
+    }
+
+    fn erase(&mut self) -> Result<(), FlashError> {
+        with_core0_parked(|| unsafe {
+            rp2040_flash::flash::flash_range_erase(SETTINGS_OFFSET, SECTOR_SIZE as u32, true);
+        });
+        return Ok(());
+    }
+}
+
+/// Runs `f` on core1 while core0 waits in RAM with interrupts disabled.
+fn with_core0_parked<F: FnOnce()>(f: F) {
+    PARK_REQUEST.store(true, Ordering::Release);
+    while !PARKED.load(Ordering::Acquire) {}
+
+    cortex_m::interrupt::free(|_| f());
+
+    PARK_REQUEST.store(false, Ordering::Release);
+    while PARKED.load(Ordering::Acquire) {}
+}
+
+/// Called from the core0 loop, waits there while core1 works with the flash.
+pub fn park_if_requested() {
+    if PARK_REQUEST.load(Ordering::Acquire) {
+        park();
+    }
+}
+
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+fn park() {
+    cortex_m::interrupt::disable();
+    PARKED.store(true, Ordering::Release);
+    while PARK_REQUEST.load(Ordering::Acquire) {}
+    PARKED.store(false, Ordering::Release);
+    unsafe { cortex_m::interrupt::enable() };
+}
@@ -1,48 +1,117 @@
 #![no_std]
 #![no_main]
 
-mod config;
-
-use config::Config;
-
 use bsp::entry;
 use bsp::hal::fugit::RateExtU32;
 #[allow(unused_imports)]
 use defmt::*;
 use defmt_rtt as _;
-use embedded_hal::blocking::spi::Transfer;
-use embedded_hal::digital::v2::{OutputPin, ToggleableOutputPin};
 use panic_probe as _;
 
-use protocol;
-use protocol::adis;
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicBool, Ordering};
+use cortex_m::interrupt::Mutex;
+
+use firmware_core::protocol;
+use firmware_core::{Batcher, BurstConsumer, BurstProducer, BurstQueue, Firmware, Sensor};
+use firmware_core::{CommandConsumer, CommandQueue, SettingsStore, StatusLed, Supervisor, TxQueue};
+use firmware_core::{BURST32_WORDS, SERIAL_PACKET_SIZE};
 
 use rp_pico as bsp;
 use usb_device as usbd;
 
 use bsp::hal;
-use cortex_m::delay::Delay;
 use hal::clocks::{init_clocks_and_plls, Clock};
+use hal::dma::DMAExt;
 use hal::gpio;
-use hal::timer::{Instant, Timer};
+use hal::pac;
+use hal::pac::interrupt;
+use hal::timer::{Alarm, Timer};
+
+use embedded_hal::digital::v2::OutputPin;
+
+mod dma;
+mod flash;
+mod spi;
 
 const XTAL_FREQ_HZ: u32 = 12_000_000;
 
-const SPI_FREQUENCY_HZ: u32 = 950_000;
+/// Board restarts when either core stops running its loop for this long.
+const WATCHDOG_TIMEOUT_US: u32 = 1_000_000;
+/// Time for the USB to send acknowledgement of bootloader entry before the reboot.
+const BOOTLOADER_DELAY_US: u64 = 50_000;
+/// The LED shows activity of the ROM bootloader.
+const BOOTLOADER_ACTIVITY_PIN_MASK: u32 = 1 << 25;
+
+/// How often the supervisor checks the sensors and the heartbeat is checked to be due.
+const SUPERVISION_PERIOD_US: u64 = 10_000;
 
 const VID: u16 = protocol::VID_PID.0;
 const PID: u16 = protocol::VID_PID.1;
 
-const SERIAL_PACKET_SIZE: usize = 64;
+type Spi1Pins = (
+    gpio::Pin<gpio::bank0::Gpio11, gpio::FunctionSpi, gpio::PullDown>,
+    gpio::Pin<gpio::bank0::Gpio12, gpio::FunctionSpi, gpio::PullDown>,
+    gpio::Pin<gpio::bank0::Gpio14, gpio::FunctionSpi, gpio::PullDown>,
+);
+type Imu0 = Sensor<
+    spi::SensorSpi<pac::SPI1, Spi1Pins>,
+    gpio::Pin<gpio::bank0::Gpio15, gpio::FunctionSioOutput, gpio::PullDown>,
+>;
+type DrPin0 = gpio::Pin<gpio::bank0::Gpio21, gpio::FunctionSioInput, gpio::PullDown>;
+
+#[cfg(feature = "second-sensor")]
+type Spi0Pins = (
+    gpio::Pin<gpio::bank0::Gpio19, gpio::FunctionSpi, gpio::PullDown>,
+    gpio::Pin<gpio::bank0::Gpio16, gpio::FunctionSpi, gpio::PullDown>,
+    gpio::Pin<gpio::bank0::Gpio18, gpio::FunctionSpi, gpio::PullDown>,
+);
+#[cfg(feature = "second-sensor")]
+type Imu1 = Sensor<
+    spi::SensorSpi<pac::SPI0, Spi0Pins>,
+    gpio::Pin<gpio::bank0::Gpio27, gpio::FunctionSioOutput, gpio::PullDown>,
+>;
+#[cfg(feature = "second-sensor")]
+type DrPin1 = gpio::Pin<gpio::bank0::Gpio22, gpio::FunctionSioInput, gpio::PullDown>;
+#[cfg(not(feature = "second-sensor"))]
+type Imu1 = firmware_core::NoImu;
+
+/// Everything the acquisition interrupts on core1 work with, the core1 loop borrows the firmware for host messages.
+///
+/// Data ready edge requests the burst, the stall alarm starts DMA reading its words
+/// and the DMA interrupt puts the burst into the queue.
+struct Acquisition {
+    firmware: Firmware<Imu0, Imu1, PicoClock>,
+    dr_pin: DrPin0,
+    #[cfg(feature = "second-sensor")]
+    dr_pin_1: DrPin1,
+    bursts: BurstProducer<'static>,
+    dma: dma::BurstDma,
+    stall: hal::timer::Alarm0,
+    /// Burst requested from the sensor, waiting for the stall time.
+    requested: Option<(protocol::SensorIndex, usize)>,
+    /// Sensors with data ready edge not handled yet, only one is read at a time.
+    waiting: [bool; 2],
+    /// Messages that did not fit into the queue since the last `Overflow` report.
+    dropped: u32,
+    store: SettingsStore<flash::PicoFlash>,
+    supervisor: Supervisor,
+}
 
-const SPI_DATA_DELAY_US: u64 = 16;
+/// Used only on core1, the critical sections keep its interrupts away from each other.
+static ACQUISITION: Mutex<RefCell<Option<Acquisition>>> = Mutex::new(RefCell::new(None));
+
+/// Set by core1 in every iteration of its loop, core0 feeds the watchdog only when it sees it.
+static CORE1_ALIVE: AtomicBool = AtomicBool::new(false);
+
+/// Set by core0 once the host configured the USB device, stored bursts start then.
+static USB_CONFIGURED: AtomicBool = AtomicBool::new(false);
 
 #[entry]
 fn main() -> ! {
     let mut pac = hal::pac::Peripherals::take().unwrap();
-    let core = hal::pac::CorePeripherals::take().unwrap();
     let mut watchdog = hal::Watchdog::new(pac.WATCHDOG);
-    let sio = hal::Sio::new(pac.SIO);
+    let mut sio = hal::Sio::new(pac.SIO);
 
     // External high-speed crystal on the pico board is 12Mhz
     let external_xtal_freq_hz = XTAL_FREQ_HZ;
@@ -58,8 +127,21 @@ fn main() -> ! {
     .ok()
     .unwrap();
 
-    let mut delay = Delay::new(core.SYST, clocks.system_clock.freq().to_Hz());
-    let timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+    let mut timer = Timer::new(pac.TIMER, &mut pac.RESETS, &clocks);
+    let mut stall = timer.alarm_0().unwrap();
+    stall.enable_interrupt();
+
+    let dma_channels = pac.DMA.split(&mut pac.RESETS);
+    let dma_buf = cortex_m::singleton!(: [u16; BURST32_WORDS] = [0; BURST32_WORDS]).unwrap();
+    let dma = dma::BurstDma::new((dma_channels.ch0, dma_channels.ch1), dma_buf);
+
+    // flash can not be read while the unique id command runs, nothing else may touch it
+    let mut unique_id = [0; 8];
+    cortex_m::interrupt::free(|_| unsafe {
+        rp2040_flash::flash::flash_unique_id(&mut unique_id, true)
+    });
+    let serial_number = firmware_core::serial_number(&unique_id);
+    let serial_number = core::str::from_utf8(&serial_number).unwrap();
 
     let pins = bsp::Pins::new(
         pac.IO_BANK0,
@@ -68,8 +150,8 @@ fn main() -> ! {
         &mut pac.RESETS,
     );
 
-    let mut _led_pin = pins.led.into_push_pull_output();
-    let mut n_rst = pins
+    let mut led_pin = pins.led.into_push_pull_output();
+    let n_rst = pins
         .gpio15
         .into_push_pull_output_in_state(gpio::PinState::High);
 
@@ -80,12 +162,13 @@ fn main() -> ! {
     let ncs = pins.gpio13.into_function::<gpio::FunctionSpi>();
 
     let spi = hal::spi::Spi::<_, _, _, 16>::new(pac.SPI1, (mosi, miso, sclk));
-    let mut spi = spi.init(
+    let spi = spi.init(
         &mut pac.RESETS,
         clocks.peripheral_clock.freq(),
-        SPI_FREQUENCY_HZ.Hz(),
+        protocol::SpiTiming::default().frequency_hz.Hz(),
         &embedded_hal::spi::MODE_3,
     );
+    let spi = spi::SensorSpi::new(spi, clocks.peripheral_clock.freq());
 
     let usb_bus = usbd::class_prelude::UsbBusAllocator::new(hal::usb::UsbBus::new(
         pac.USBCTRL_REGS,
@@ -102,202 +185,423 @@ fn main() -> ! {
     )
     .manufacturer("aa4cc")
     .product("ADIS IMU Breakout")
+    .serial_number(serial_number)
     .device_class(usbd_serial::USB_CLASS_CDC)
     .build();
 
     let _sync_pin = pins.gpio20;
 
-    let mut dr_pin = pins.gpio21;
-    dr_pin.set_interrupt_enabled(gpio::Interrupt::EdgeHigh, true);
-
-    let mut config = Config::default();
-    let mut cobs_buf: protocol::CobsAccumulator<256> = protocol::CobsAccumulator::new();
+    let dr_pin = pins.gpio21.into_pull_down_input();
+
+    // timer only reads the counter, core0 keeps a copy for the batch deadline
+    let firmware = Firmware::new(spi, n_rst, PicoClock(timer));
+
+    // second sensor on SPI0
+    #[cfg(feature = "second-sensor")]
+    let (firmware, dr_pin_1) = {
+        let n_rst = pins
+            .gpio27
+            .into_push_pull_output_in_state(gpio::PinState::High);
+
+        let sclk = pins.gpio18.into_function::<gpio::FunctionSpi>();
+        let mosi = pins.gpio19.into_function::<gpio::FunctionSpi>();
+        let miso = pins.gpio16.into_function::<gpio::FunctionSpi>();
+        #[allow(unused)]
+        let ncs = pins.gpio17.into_function::<gpio::FunctionSpi>();
+
+        let spi = hal::spi::Spi::<_, _, _, 16>::new(pac.SPI0, (mosi, miso, sclk));
+        let spi = spi.init(
+            &mut pac.RESETS,
+            clocks.peripheral_clock.freq(),
+            protocol::SpiTiming::default().frequency_hz.Hz(),
+            &embedded_hal::spi::MODE_3,
+        );
+        let spi = spi::SensorSpi::new(spi, clocks.peripheral_clock.freq());
+
+        let dr_pin = pins.gpio22.into_pull_down_input();
+
+        (firmware.with_second_sensor(spi, n_rst), dr_pin)
+    };
+
+    // stored configuration is in place before the host says anything, bursts wait for USB enumeration,
+    // they do not start with configuration the sensors did not take, the LED and `Status` tell it
+    let store = SettingsStore::new(flash::PicoFlash);
+    let mut firmware = firmware;
+    let mut autostart = [false; 2];
+    if let Some(mut settings) = store.load() {
+        let start = settings.take_autostart();
+        if firmware.apply_stored(&settings) {
+            autostart = start;
+        }
+    }
 
+    let queue: &'static mut BurstQueue =
+        cortex_m::singleton!(: BurstQueue = BurstQueue::new()).unwrap();
+    let (producer, mut bursts) = queue.split();
+    let queue: &'static mut CommandQueue =
+        cortex_m::singleton!(: CommandQueue = CommandQueue::new()).unwrap();
+    let (mut commands, command_consumer) = queue.split();
+
+    let acquisition = Acquisition {
+        firmware,
+        dr_pin,
+        #[cfg(feature = "second-sensor")]
+        dr_pin_1,
+        bursts: producer,
+        dma,
+        stall,
+        requested: None,
+        waiting: [false; 2],
+        dropped: 0,
+        store,
+        supervisor: Supervisor::new(),
+    };
+
+    // sensors are read on core1, USB enumeration and host traffic can not delay them
+    let mut multicore = hal::multicore::Multicore::new(&mut pac.PSM, &mut pac.PPB, &mut sio.fifo);
+    let core1 = &mut multicore.cores()[1];
+    let core1_stack =
+        cortex_m::singleton!(: hal::multicore::Stack<4096> = hal::multicore::Stack::new()).unwrap();
+    core1
+        .spawn(&mut core1_stack.mem, move || {
+            core1_task(acquisition, command_consumer, autostart)
+        })
+        .unwrap();
+
+    watchdog.pause_on_debug(true);
+    watchdog.start(hal::fugit::MicrosDurationU32::micros(WATCHDOG_TIMEOUT_US));
+
+    let mut decoder = firmware_core::Decoder::new();
+    let mut sender = Sender::new();
+    let mut led = StatusLed::new();
+    let mut bootloader_at = None;
     loop {
+        flash::park_if_requested();
+        if CORE1_ALIVE.swap(false, Ordering::AcqRel) {
+            watchdog.feed();
+        }
+
         if usb_device.poll(&mut [&mut serial]) {
             let mut rcv_buf = [0; SERIAL_PACKET_SIZE];
             let rcv_size = serial.read(&mut rcv_buf).unwrap_or(0);
 
-            let mut window = &rcv_buf[..rcv_size];
-
-            let mut response: protocol::Vec<_, 8> = protocol::Vec::new();
-
-
-            'cobs: while !window.is_empty() {
-                window = match cobs_buf.feed::<protocol::Message>(&window) {
-                    protocol::FeedResult::Consumed => break 'cobs,
-                    protocol::FeedResult::OverFull(new_wind) => new_wind,
-                    protocol::FeedResult::DeserError(new_wind) => new_wind,
-                    protocol::FeedResult::Success { data, remaining } => {
-                        match data {
-                            protocol::Message::CFG(cfg) => {
-                                let mut new_config = config;
-
-                                // switch the various config messages
-                                match cfg {
-                                    protocol::cfg::CFG::BurstEn(v) => {
-                                        new_config.burst_enabled = v;
-                                        response.push(data).ok();
-                                    }
-                                    protocol::cfg::CFG::Burst32(v) => {
-                                        new_config.msc_ctrl.burst32 = v
-                                    }
-                                    protocol::cfg::CFG::BurstSel(v) => {
-                                        new_config.msc_ctrl.burst_sel = v
-                                    }
-                                    protocol::cfg::CFG::LinearAccelerationCompensation(v) => {
-                                        new_config.msc_ctrl.lac = v
-                                    }
-                                    protocol::cfg::CFG::PointOfPercussionAlignment(v) => {
-                                        new_config.msc_ctrl.popa = v
-                                    }
-                                    protocol::cfg::CFG::SensorBandwidth(v) => {
-                                        new_config.msc_ctrl.bw = v
-                                    }
-                                    protocol::cfg::CFG::SyncPolarity(v) => {
-                                        new_config.msc_ctrl.sync_pol = v
-                                    }
-                                    protocol::cfg::CFG::DataReadyPolarity(v) => {
-                                        new_config.msc_ctrl.dr_pol = v
-                                    }
-                                }
-
-                                // check if config changed, if not it is ok (also burst en is pretty direct)
-                                if config == new_config || config.msc_ctrl == new_config.msc_ctrl {
-                                    config = new_config;
-                                    response.push(data).ok();
-                                } else {
-                                    // it has changed something inside msc_ctrl, it has to be written into imu
-                                    let change = adis::memorymap::to_write(
-                                        adis::memorymap::MSC_CTRL,
-                                        new_config.msc_ctrl.into(),
-                                    );
-                                    for d in change {
-                                        transfer(&mut spi, d, &timer).ok();
-                                    }
-
-                                    // wait for the change to propagate
-                                    delay.delay_ms(1);
-
-                                    // check the value inside imu
-                                    if let Ok(r) = transfer(
-                                        &mut spi,
-                                        adis::memorymap::request(adis::memorymap::MSC_CTRL),
-                                        &timer,
-                                    ) {
-                                        // if the value inside imu is correct, ack the message
-                                        if new_config.msc_ctrl == r.into() {
-                                            config = new_config;
-                                            response.push(data).ok();
-                                        }
-                                    }
-                                }
-                            }
-
-                            protocol::Message::RQR(rqr) => {
-                                if let Ok(r) = request_response(&mut spi, rqr, &timer) {
-                                    response.push(protocol::Message::RQR(r)).ok();
-                                }
-                            }
-
-                            protocol::Message::RST => {
-                                n_rst.set_low().ok();
-                                delay.delay_us(50);
-                                n_rst.set_high().ok();
-
-                                config = config::Config::default();
-
-                                response.push(data).ok();
-                            }
-
-                            protocol::Message::B16(..) => {}
-
-                            protocol::Message::B32(..) => {}
-
-                            protocol::Message::ERR(..) => {}
-                        }
-
-                        remaining
-                    }
-                };
-            }
-
-            let mut response_buffer: protocol::Vec<_, 64> = protocol::Vec::new();
-            loop {
-                if let Some(response_msg) = response.last() {
-                    if let Ok(local_response_vec) =
-                        protocol::to_vec_cobs::<_, SERIAL_PACKET_SIZE>(&response_msg)
-                    {
-                        if response_buffer
-                            .extend_from_slice(&local_response_vec)
-                            .is_ok()
-                        {
-                            response.pop();
-                        } else {
-                            break;
-                        }
-                    }
-                } else {
-                    break;
+            // messages not fitting into the queue are never confirmed, the host sends them again
+            for message in decoder.feed(&rcv_buf[..rcv_size]) {
+                if message == protocol::Message::EnterBootloader {
+                    sender.tx.push(&message);
+                    bootloader_at.get_or_insert(timer.get_counter().ticks() + BOOTLOADER_DELAY_US);
+                    continue;
                 }
+                commands.enqueue(message).ok();
             }
-            serial.write(&response_buffer).ok();
         }
+        let usb_configured = usb_device.state() == usbd::device::UsbDeviceState::Configured;
+        if usb_configured {
+            USB_CONFIGURED.store(true, Ordering::Release);
+        }
+
+        let now_us = timer.get_counter().ticks();
+        sender.send(&mut serial, &mut bursts, now_us);
 
-        if config.burst_enabled && dr_pin.interrupt_status(gpio::Interrupt::EdgeHigh) {
-            transfer(
-                &mut spi,
-                adis::memorymap::request(adis::memorymap::GLOB_CMD),
-                &timer,
-            )
-            .ok();
-
-            let burst = match config.msc_ctrl.burst32 {
-                adis::msc_ctrl::Burst32::Disabled => {
-                    let mut imu_out = [0; 10];
-                    imu_out.copy_from_slice(spi.transfer(&mut [0; 10]).unwrap_or(&[0; 10]));
-                    let b: adis::burstmem::BurstMemory16 = imu_out.into();
-                    protocol::Message::B16(config.msc_ctrl.burst_sel, b)
-                },
-                adis::msc_ctrl::Burst32::Enabled => {
-                    let mut imu_out = [0; 16];
-                    imu_out.copy_from_slice(spi.transfer(&mut [0; 16]).unwrap_or(&[0; 16]));
-                    let b: adis::burstmem::BurstMemory32 = imu_out.into();
-                    protocol::Message::B32(config.msc_ctrl.burst_sel, b)
-                
-                },      
-            };
-
-            let data = protocol::to_vec_cobs::<_, 64>(&burst)
-                .unwrap_or(protocol::Vec::new());
-
-            serial.write(&data).ok();
-
-            dr_pin.clear_interrupt(gpio::Interrupt::EdgeHigh);
+        if bootloader_at.is_some_and(|at| now_us >= at) {
+            hal::rom_data::reset_to_usb_boot(BOOTLOADER_ACTIVITY_PIN_MASK, 0);
+        }
+
+        // the board state is known from the status heartbeat passing through
+        if led
+            .indication(&sender.status, usb_configured, now_us)
+            .is_on(now_us)
+        {
+            led_pin.set_high().ok();
+        } else {
+            led_pin.set_low().ok();
         }
     }
 }
 
-// Warning: do not use this function in multiple concurrent tasks/threads/interrupts, it uses static value
-pub fn transfer(spi: &mut impl Transfer<u16>, data: u16, timer: &Timer) -> Result<u16, ()> {
-    static mut LAST_SPI_COMM: Option<Instant> = None;
+/// Acquisition on core1, its interrupts read the sensors and the loop handles host messages from core0.
+///
+/// Bursts of `autostart` sensors are enabled once USB is configured.
+fn core1_task(
+    mut acquisition: Acquisition,
+    mut commands: CommandConsumer<'static>,
+    mut autostart: [bool; 2],
+) -> ! {
+    // GPIO interrupts go to the core that enables them
+    acquisition
+        .dr_pin
+        .set_interrupt_enabled(gpio::Interrupt::EdgeHigh, true);
+    #[cfg(feature = "second-sensor")]
+    acquisition
+        .dr_pin_1
+        .set_interrupt_enabled(gpio::Interrupt::EdgeHigh, true);
+
+    let timer = acquisition.firmware.clock().0;
+    cortex_m::interrupt::free(|cs| {
+        ACQUISITION.borrow(cs).replace(Some(acquisition));
+    });
+    unsafe {
+        pac::NVIC::unmask(pac::Interrupt::IO_IRQ_BANK0);
+        pac::NVIC::unmask(pac::Interrupt::TIMER_IRQ_0);
+        pac::NVIC::unmask(pac::Interrupt::DMA_IRQ_0);
+    }
+
+    let mut next_supervision_us = 0;
     loop {
-        if let Some(inst) = unsafe { LAST_SPI_COMM } {
-            if let Some(d) = timer.get_counter().checked_duration_since(inst) {
-                if d.to_micros() >= SPI_DATA_DELAY_US {
-                    break;
+        CORE1_ALIVE.store(true, Ordering::Release);
+
+        let now_us = timer.get_counter().ticks();
+        if now_us >= next_supervision_us {
+            next_supervision_us = now_us + SUPERVISION_PERIOD_US;
+            with_acquisition(|a| {
+                a.supervise();
+                if let Some(status) = a.firmware.poll_status() {
+                    a.enqueue(status);
                 }
+            });
+        }
+
+        if autostart.contains(&true) && USB_CONFIGURED.load(Ordering::Acquire) {
+            with_acquisition(|a| {
+                a.settle();
+                for (sensor, _) in autostart.iter().enumerate().filter(|(_, start)| **start) {
+                    let burst_en = protocol::cfg::CFG::BurstEn(true);
+                    a.firmware.handle(protocol::Message::CFG(
+                        sensor as protocol::SensorIndex,
+                        burst_en,
+                    ));
+                }
+            });
+            autostart = [false; 2];
+        }
+
+        if let Some(message) = commands.dequeue() {
+            // configuration talks to the sensors, acquisition waits until it is done
+            with_acquisition(|a| {
+                a.settle();
+                // the host takes care of the sensors it configures
+                a.supervisor.host_message(&message);
+                if let Some(response) = a.firmware.handle_with_store(message, &mut a.store) {
+                    a.enqueue(response);
+                }
+            });
+        }
+    }
+}
+
+/// Sends queued bursts, batched, and responses to the host.
+struct Sender {
+    batcher: Batcher,
+    tx: TxQueue,
+    /// Last status sent.
+    status: protocol::Status,
+}
+
+impl Sender {
+    fn new() -> Self {
+        return Self {
+            batcher: Batcher::new(),
+            tx: TxQueue::new(),
+            status: protocol::Status::default(),
+        };
+    }
+
+    /// Moves messages into the TX queue and writes it while the USB takes the data.
+    fn send<B: usbd::bus::UsbBus>(
+        &mut self,
+        serial: &mut usbd_serial::SerialPort<B>,
+        queue: &mut BurstConsumer<'static>,
+        now_us: u64,
+    ) {
+        while let Some(message) = queue.dequeue() {
+            if let protocol::Message::Status(status) = message {
+                self.status = status;
             }
-        } else {
-            break;
+            for m in self.batcher.push(message, now_us) {
+                self.tx.push(&m);
+            }
+        }
+        if let Some(batch) = self.batcher.poll(now_us) {
+            self.tx.push(&batch);
+        }
+
+        self.tx.write(|bytes| serial.write(bytes).unwrap_or(0));
+    }
+}
+
+#[interrupt]
+fn IO_IRQ_BANK0() {
+    with_acquisition(Acquisition::data_ready);
+}
+
+#[interrupt]
+fn TIMER_IRQ_0() {
+    with_acquisition(Acquisition::stalled);
+}
+
+#[interrupt]
+fn DMA_IRQ_0() {
+    with_acquisition(Acquisition::transferred);
+}
+
+fn with_acquisition(f: impl FnOnce(&mut Acquisition)) {
+    cortex_m::interrupt::free(|cs| {
+        if let Some(acquisition) = ACQUISITION.borrow(cs).borrow_mut().as_mut() {
+            f(acquisition);
+        }
+    });
+}
+
+impl Acquisition {
+    /// Notes sensors with data ready edge and requests burst from one of them.
+    fn data_ready(&mut self) {
+        let now_us = firmware_core::Clock::now_us(self.firmware.clock());
+        if self.dr_pin.interrupt_status(gpio::Interrupt::EdgeHigh) {
+            self.waiting[0] = true;
+            self.supervisor.data_ready(0, now_us);
+            self.dr_pin.clear_interrupt(gpio::Interrupt::EdgeHigh);
+        }
+
+        #[cfg(feature = "second-sensor")]
+        if self.dr_pin_1.interrupt_status(gpio::Interrupt::EdgeHigh) {
+            self.waiting[1] = true;
+            self.supervisor.data_ready(1, now_us);
+            self.dr_pin_1.clear_interrupt(gpio::Interrupt::EdgeHigh);
+        }
+
+        self.request_next();
+    }
+
+    /// Stall time after the request passed, DMA reads the burst words.
+    fn stalled(&mut self) {
+        self.stall.clear_interrupt();
+        if let Some((sensor, len)) = self.requested.take() {
+            self.read(sensor, len);
+        }
+    }
+
+    /// DMA read the words, the burst goes into the queue and next waiting sensor is requested.
+    fn transferred(&mut self) {
+        self.collect();
+        self.request_next();
+    }
+
+    /// Finishes burst in progress, the firmware may use the SPI afterwards.
+    fn settle(&mut self) {
+        if let Some((sensor, len)) = self.requested.take() {
+            let stall_us = self.stall_us(sensor);
+            let clock = self.firmware.clock();
+            let deadline = firmware_core::Clock::now_us(clock) + stall_us as u64;
+            while firmware_core::Clock::now_us(clock) < deadline {}
+
+            self.read(sensor, len);
+        }
+
+        while !self.dma.is_idle() {
+            self.collect();
+        }
+    }
+
+    /// Starts DMA reading the requested burst, the burst is given up when it can not start.
+    fn read(&mut self, sensor: protocol::SensorIndex, len: usize) {
+        if self.dma.start(sensor, len).is_err() {
+            // the sensor SPI takes register access again only after the burst
+            self.firmware.finish_burst(sensor, &[]).ok();
+        }
+    }
+
+    fn request_next(&mut self) {
+        if self.requested.is_some() || !self.dma.is_idle() {
+            return;
+        }
+
+        for sensor in 0..self.waiting.len() {
+            if !core::mem::take(&mut self.waiting[sensor]) {
+                continue;
+            }
+
+            let sensor = sensor as protocol::SensorIndex;
+            if !self
+                .firmware
+                .config(sensor)
+                .is_some_and(|c| c.burst_enabled)
+            {
+                continue;
+            }
+
+            // no room for the burst, it is not even read
+            if !self.bursts.ready() {
+                self.dropped = self.dropped.saturating_add(1);
+                self.firmware.count_dropped(1);
+                continue;
+            }
+
+            if let Ok(len) = self.firmware.start_burst(sensor) {
+                self.requested = Some((sensor, len));
+                self.stall
+                    .schedule(hal::fugit::MicrosDurationU32::micros(
+                        self.stall_us(sensor) as u32
+                    ))
+                    .ok();
+                return;
+            }
+        }
+    }
+
+    /// Stall time between the burst request and the data, set by the host through `Message::SpiTiming`.
+    fn stall_us(&self, sensor: protocol::SensorIndex) -> u16 {
+        return self
+            .firmware
+            .spi_timing(sensor)
+            .map_or(protocol::SpiTiming::MIN_STALL_US, |t| t.stall_us);
+    }
+
+    fn collect(&mut self) {
+        if let Some((sensor, words)) = self.dma.finish() {
+            if let Ok(burst) = self.firmware.finish_burst(sensor, words) {
+                let report = self.supervisor.burst(&burst);
+                self.enqueue(burst);
+                if let Some(report) = report {
+                    self.enqueue(report);
+                }
+            }
+        }
+    }
+
+    /// Resets stuck sensors and restores them after the start-up time, the host gets the health reports.
+    fn supervise(&mut self) {
+        self.settle();
+        let now_us = firmware_core::Clock::now_us(self.firmware.clock());
+        for report in self.supervisor.poll(&mut self.firmware, now_us) {
+            self.enqueue(report);
+        }
+    }
+
+    /// Queues message for core0, reporting messages dropped before it first.
+    fn enqueue(&mut self, message: protocol::Message) {
+        if self.dropped > 0
+            && self
+                .bursts
+                .enqueue(protocol::Message::Overflow {
+                    source: protocol::OverflowSource::Queue,
+                    dropped: self.dropped,
+                })
+                .is_ok()
+        {
+            self.dropped = 0;
+        }
+
+        if self.bursts.enqueue(message).is_err() {
+            self.dropped = self.dropped.saturating_add(1);
+            self.firmware.count_dropped(1);
         }
     }
-    let res = spi.transfer(&mut [data]).map_err(|_| ())?[0];
-    unsafe { LAST_SPI_COMM = Some(timer.get_counter()) };
-    return Ok(res);
 }
 
-pub fn request_response(spi: &mut impl Transfer<u16>, data: u16, timer: &Timer) -> Result<u16, ()> {
-    transfer(spi, data, timer)?;
-    return transfer(spi, 0, timer);
+struct PicoClock(Timer);
+
+impl firmware_core::Clock for PicoClock {
+    fn now_us(&self) -> u64 {
+        return self.0.get_counter().ticks();
+    }
 }
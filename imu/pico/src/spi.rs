@@ -0,0 +1,41 @@
+//! SPI of the sensors with clock the firmware can change at runtime.
+
+use hal::fugit::{HertzU32, RateExtU32};
+use hal::spi::{Enabled, Spi, SpiDevice, ValidSpiPinout};
+use rp_pico::hal;
+
+use embedded_hal::blocking::spi::Transfer;
+
+/// Enabled 16-bit SPI, remembers the peripheral clock the baudrate is divided from.
+///
+/// The firmware keeps the sensor timing by wrapping it in `AdisSpi`, chip select is framed by the peripheral.
+pub struct SensorSpi<D: SpiDevice, P: ValidSpiPinout<D>> {
+    spi: Spi<Enabled, D, P, 16>,
+    peripheral_freq: HertzU32,
+}
+
+impl<D: SpiDevice, P: ValidSpiPinout<D>> SensorSpi<D, P> {
+    pub fn new(spi: Spi<Enabled, D, P, 16>, peripheral_freq: HertzU32) -> Self {
+        return Self {
+            spi,
+            peripheral_freq,
+        };
+    }
+}
+
+impl<D: SpiDevice, P: ValidSpiPinout<D>> Transfer<u16> for SensorSpi<D, P> {
+    type Error = <Spi<Enabled, D, P, 16> as Transfer<u16>>::Error;
+
+    fn transfer<'w>(&mut self, words: &'w mut [u16]) -> Result<&'w [u16], Self::Error> {
+        return self.spi.transfer(words);
+    }
+}
+
+impl<D: SpiDevice, P: ValidSpiPinout<D>> firmware_core::SpiClock for SensorSpi<D, P> {
+    fn set_frequency(&mut self, frequency_hz: u32) -> u32 {
+        return self
+            .spi
+            .set_baudrate(self.peripheral_freq, frequency_hz.Hz())
+            .to_Hz();
+    }
+}
@@ -14,9 +14,13 @@ use std::sync::Arc;
 use driver::protocol::DEFAULT_BAUDRATE;
 use driver::protocol::VID_PID as DEFAULT_VID_PID;
 
+const STREAM_CAPACITY: usize = 4096;
+const SIGNAL_CHECK_PERIOD: driver::Duration = driver::Duration::from_millis(100);
+
 #[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
 struct LogOutput {
     timestamp_pc: f64,
+    sensor: driver::protocol::SensorIndex,
     data: driver::protocol::adis::BurstData,
 }
 
@@ -24,6 +28,17 @@ type MainResult = Result<(), ()>;
 fn main() -> MainResult {
     let args = args::Args::parse();
 
+    if args.list {
+        for device in driver::list_devices().expect("Could not list devices.") {
+            println!(
+                "{}\t{}",
+                device.port_name,
+                device.serial_number.unwrap_or_default()
+            );
+        }
+        return Ok(());
+    }
+
     let timeout = args
         .timeout_ns
         .map(|nanos| driver::Duration::from_nanos(nanos));
@@ -33,36 +48,46 @@ fn main() -> MainResult {
 
     let mut adis = if let Some(path) = args.device {
         driver::AdisDevice::from_device_name(path, args.baud_rate, version, timeout)
+    } else if let Some(serial_number) = args.serial_number {
+        driver::AdisDevice::from_serial_number(&serial_number, args.baud_rate, version, timeout)
     } else {
         driver::AdisDevice::from_vid_pid(args.vid, args.pid, args.baud_rate, version, timeout)
     }
     .expect("Could not connect to device.");
 
+    if args.bootloader {
+        adis.send_enter_bootloader()
+            .expect("Could not enter bootloader.");
+        return Ok(());
+    }
+
     let log_path = Path::new(args.log_path.as_str())
         .with_file_name(args.log_name)
         .with_extension("txt");
 
-    adis.send_restart().expect("Could not restart device.");
-
-    let cfg_burst_mode = match args.burst_mode {
-        16 => driver::protocol::cfg::CFG::Burst32(driver::protocol::cfg::Burst32::Disabled),
-        32 => driver::protocol::cfg::CFG::Burst32(driver::protocol::cfg::Burst32::Enabled),
-        _ => panic!("Invalid burst mode, only 16 and 32 are valid options."),
-    };
-    adis.send_config(cfg_burst_mode)
-        .expect("Could not set burst mode.");
-
-    let cfg_burst_sel = match args.burst_sel {
-        0 => driver::protocol::cfg::CFG::BurstSel(driver::protocol::cfg::BurstSel::Sel0),
-        1 => driver::protocol::cfg::CFG::BurstSel(driver::protocol::cfg::BurstSel::Sel1),
-        _ => panic!("Invalid burst sel, only 0 and 1 are valid options."),
-    };
-    adis.send_config(cfg_burst_sel)
-        .expect("Could not set burst sel.");
-
-    let cfg_burst_en = driver::protocol::cfg::CFG::BurstEn(true);
-    adis.send_config(cfg_burst_en)
-        .expect("Could not enable burst.");
+    if !args.attach {
+        adis.send_restart().expect("Could not restart device.");
+
+        let cfg_burst_mode = match args.burst_mode {
+            16 => driver::protocol::cfg::CFG::Burst32(driver::protocol::cfg::Burst32::Disabled),
+            32 => driver::protocol::cfg::CFG::Burst32(driver::protocol::cfg::Burst32::Enabled),
+            _ => panic!("Invalid burst mode, only 16 and 32 are valid options."),
+        };
+        adis.send_config(cfg_burst_mode)
+            .expect("Could not set burst mode.");
+
+        let cfg_burst_sel = match args.burst_sel {
+            0 => driver::protocol::cfg::CFG::BurstSel(driver::protocol::cfg::BurstSel::Sel0),
+            1 => driver::protocol::cfg::CFG::BurstSel(driver::protocol::cfg::BurstSel::Sel1),
+            _ => panic!("Invalid burst sel, only 0 and 1 are valid options."),
+        };
+        adis.send_config(cfg_burst_sel)
+            .expect("Could not set burst sel.");
+
+        let cfg_burst_en = driver::protocol::cfg::CFG::BurstEn(true);
+        adis.send_config(cfg_burst_en)
+            .expect("Could not enable burst.");
+    }
 
 
     let out_file = File::create(log_path).expect("Could not create file.");
@@ -83,20 +108,24 @@ fn main() -> MainResult {
     )
     .expect("Could not hook systemd signal.");
 
+    let mut stream = driver::AdisStream::new(adis, STREAM_CAPACITY, driver::OverflowPolicy::Block);
+
     while !end_manual.load(Ordering::Relaxed) && !end_systemd.load(Ordering::Relaxed) {
-        let messages = adis.expect_burst().expect("There was error while reading.");
+        let Some(burst) = stream.recv_timeout(SIGNAL_CHECK_PERIOD) else {
+            continue;
+        };
+        let burst = burst.expect("There was error while reading.");
         let reception_time = SystemTime::now();
 
-        for m in messages {
-            let out = LogOutput {
-                timestamp_pc: reception_time
-                    .duration_since(UNIX_EPOCH)
-                    .expect("Timing error in PC.")
-                    .as_secs_f64(),
-                data: m,
-            };
-            writer.write(format!("{:#?}\n", out).as_bytes()).expect("Could not write to file.");
-        }
+        let out = LogOutput {
+            timestamp_pc: reception_time
+                .duration_since(UNIX_EPOCH)
+                .expect("Timing error in PC.")
+                .as_secs_f64(),
+            sensor: burst.sensor,
+            data: burst.data,
+        };
+        writer.write(format!("{:#?}\n", out).as_bytes()).expect("Could not write to file.");
     }
 
     writer.flush().expect("Writer was not able to flush data.");
@@ -0,0 +1,68 @@
+pub use clap::Parser;
+
+use simulator::{Motion, Signal};
+
+#[derive(Parser, Debug, Clone)]
+#[command(author, version, about, long_about = None)]
+pub struct Args {
+    /// path of symlink pointing to the created serial port
+    #[arg(long)]
+    pub link: Option<String>,
+
+    /// device number
+    #[arg(long, default_value_t = 16505)]
+    pub board_id: u32,
+
+    /// device version
+    #[arg(long, default_value_t = 1)]
+    pub board_version: u32,
+
+    /// constant angular rates in deg/s
+    #[arg(long, value_delimiter = ',', default_values_t = [0.0, 0.0, 0.0])]
+    pub gyro: Vec<f64>,
+
+    /// constant accelerations in m/s^2
+    #[arg(long, value_delimiter = ',', default_values_t = [0.0, 0.0, simulator::motion::STANDARD_GRAVITY])]
+    pub accl: Vec<f64>,
+
+    /// amplitude in deg/s of sinusoidal rotation added around every axis
+    #[arg(long, default_value_t = 0.0)]
+    pub sine_amplitude: f64,
+
+    /// frequency in Hz of sinusoidal rotation
+    #[arg(long, default_value_t = 1.0)]
+    pub sine_frequency: f64,
+
+    /// standard deviation of gyro noise in deg/s
+    #[arg(long, default_value_t = 0.0)]
+    pub gyro_noise: f64,
+
+    /// standard deviation of accl noise in m/s^2
+    #[arg(long, default_value_t = 0.0)]
+    pub accl_noise: f64,
+
+    /// seed of the noise generator
+    #[arg(long, default_value_t = 1)]
+    pub seed: u64,
+
+    /// DIAG_STAT bits reported by the sensor
+    #[arg(long, default_value_t = 0)]
+    pub diag_stat: u16,
+}
+
+impl Args {
+    pub fn motion(&self) -> Motion {
+        if self.gyro.len() != 3 || self.accl.len() != 3 {
+            panic!("Invalid motion, gyro and accl need exactly 3 values.");
+        }
+
+        let mut motion = Motion::default();
+        for i in 0..3 {
+            motion.gyro[i] = Signal::sine(self.sine_amplitude, self.sine_frequency)
+                .with_bias(self.gyro[i])
+                .with_noise(self.gyro_noise);
+            motion.accl[i] = Signal::constant(self.accl[i]).with_noise(self.accl_noise);
+        }
+        return motion;
+    }
+}
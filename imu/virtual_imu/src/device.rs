@@ -0,0 +1,122 @@
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use firmware_core::{protocol, Batcher, Firmware, NoImu, Sensor, TxQueue, SERIAL_PACKET_SIZE};
+use simulator::{AdisSim, ResetPin};
+
+use super::pty::Pty;
+
+/// When the loop falls behind more than this, the missed samples are skipped instead of caught up.
+const MAX_LAG: Duration = Duration::from_millis(100);
+
+pub struct SystemClock(Instant);
+
+impl firmware_core::Clock for SystemClock {
+    fn now_us(&self) -> u64 {
+        return self.0.elapsed().as_micros() as u64;
+    }
+}
+
+/// Pico firmware running on top of simulated sensor, talking over pseudo-terminal.
+pub struct VirtualImu {
+    pty: Pty,
+    sim: AdisSim,
+    firmware: Firmware<Sensor<AdisSim, ResetPin>, NoImu, SystemClock>,
+    batcher: Batcher,
+    tx: TxQueue,
+    next_sample: Instant,
+}
+
+impl VirtualImu {
+    pub fn new(pty: Pty, sim: AdisSim) -> Self {
+        let firmware = Firmware::new(sim.clone(), sim.reset_pin(), SystemClock(Instant::now()));
+
+        return Self {
+            pty,
+            sim,
+            firmware,
+            batcher: Batcher::new(),
+            tx: TxQueue::new(),
+            next_sample: Instant::now(),
+        };
+    }
+
+    pub fn path(&self) -> &Path {
+        return self.pty.path();
+    }
+
+    /// Serves host messages and produces samples, blocks at most until the next sample is due.
+    pub fn step(&mut self) -> io::Result<()> {
+        let timeout = self.next_sample.saturating_duration_since(Instant::now());
+        if self.pty.wait_readable(timeout)? {
+            let mut rcv_buf = [0; SERIAL_PACKET_SIZE];
+            match self.pty.read(&mut rcv_buf) {
+                Ok(rcv_size) => {
+                    for response in self.firmware.receive(&rcv_buf[..rcv_size]) {
+                        self.send(response)?;
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                Err(e) => return Err(e),
+            }
+        }
+
+        let now = Instant::now();
+        if now >= self.next_sample {
+            self.sim.tick();
+
+            let period = Duration::from_secs_f64(self.sim.sample_period());
+            self.next_sample = if now > self.next_sample + MAX_LAG {
+                now + period
+            } else {
+                self.next_sample + period
+            };
+
+            if self.firmware.config(0).is_some_and(|c| c.burst_enabled) {
+                if let Ok(burst) = self.firmware.burst(0) {
+                    self.send(burst)?;
+                }
+            }
+        }
+
+        if let Some(status) = self.firmware.poll_status() {
+            self.send(status)?;
+        }
+
+        if let Some(batch) = self.batcher.poll(self.now_us()) {
+            self.tx.push(&batch);
+        }
+
+        return self.write();
+    }
+
+    /// Queues message through the batcher, as the firmware does.
+    fn send(&mut self, message: protocol::Message) -> io::Result<()> {
+        for m in self.batcher.push(message, self.now_us()) {
+            self.tx.push(&m);
+        }
+        return self.write();
+    }
+
+    fn now_us(&self) -> u64 {
+        return firmware_core::Clock::now_us(self.firmware.clock());
+    }
+
+    // as the usb serial on the pico, frames wait in the queue until the pty takes them,
+    // those that do not fit into it are dropped whole and reported by `Overflow`
+    fn write(&mut self) -> io::Result<()> {
+        let pty = &mut self.pty;
+        let mut error = None;
+        self.tx.write(|bytes| match pty.write(bytes) {
+            Ok(written) => written,
+            Err(e) => {
+                if e.kind() != io::ErrorKind::WouldBlock {
+                    error = Some(e);
+                }
+                0
+            }
+        });
+        return error.map_or(Ok(()), Err);
+    }
+}
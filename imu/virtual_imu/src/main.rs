@@ -0,0 +1,120 @@
+mod args;
+mod device;
+mod pty;
+
+use args::Parser;
+use std::fs;
+use std::os::unix::fs::symlink;
+
+use signal_hook;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use firmware_core::protocol::adis::version::AdisVersion;
+use simulator::AdisSim;
+
+type MainResult = Result<(), ()>;
+fn main() -> MainResult {
+    let args = args::Args::parse();
+
+    let version = AdisVersion::from_id(args.board_id, args.board_version).expect("Unknown board");
+
+    let sim = AdisSim::new(version).with_motion(args.motion());
+    sim.set_seed(args.seed);
+    sim.set_diag_stat(args.diag_stat);
+
+    let pty = pty::Pty::open().expect("Could not open pseudo-terminal.");
+    let mut imu = device::VirtualImu::new(pty, sim);
+
+    if let Some(link) = &args.link {
+        fs::remove_file(link).ok();
+        symlink(imu.path(), link).expect("Could not create link.");
+        println!("{}", link);
+    } else {
+        println!("{}", imu.path().display());
+    }
+
+    let end_manual = Arc::new(AtomicBool::new(false));
+    let end_systemd = Arc::new(AtomicBool::new(false));
+
+    signal_hook::flag::register(
+        signal_hook::consts::SIGINT,
+        std::sync::Arc::clone(&end_manual),
+    )
+    .expect("Could not hook manual signal.");
+
+    signal_hook::flag::register(
+        signal_hook::consts::SIGTERM,
+        std::sync::Arc::clone(&end_systemd),
+    )
+    .expect("Could not hook systemd signal.");
+
+    while !end_manual.load(Ordering::Relaxed) && !end_systemd.load(Ordering::Relaxed) {
+        imu.step().expect("There was error while serving host.");
+    }
+
+    if let Some(link) = &args.link {
+        fs::remove_file(link).ok();
+    }
+
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use driver::protocol::adis::memorymap;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    const VERSION: AdisVersion = AdisVersion::ADIS16505_1BMLZ;
+
+    #[test]
+    fn driver_receives_bursts() {
+        let pty = pty::Pty::open().unwrap();
+        let path = pty.path().to_string_lossy().into_owned();
+
+        let end = Arc::new(AtomicBool::new(false));
+        let end_device = Arc::clone(&end);
+        let device = thread::spawn(move || {
+            let mut imu = device::VirtualImu::new(pty, AdisSim::new(VERSION));
+            while !end_device.load(Ordering::Relaxed) {
+                imu.step().unwrap();
+            }
+        });
+
+        let mut adis = driver::AdisDevice::from_device_name(
+            path,
+            driver::protocol::DEFAULT_BAUDRATE,
+            VERSION,
+            Some(Duration::from_millis(10)),
+        )
+        .unwrap();
+
+        let timeout = Some(Duration::from_millis(500));
+        adis.confirmed_send(&driver::protocol::Message::RST, timeout)
+            .unwrap();
+        let prod_id = memorymap::request(memorymap::PROD_ID);
+        assert_eq!(adis.send_request_response(prod_id, timeout).unwrap(), 16505);
+        adis.confirmed_send(
+            &driver::protocol::Message::CFG(0, driver::protocol::cfg::CFG::BurstEn(true)),
+            timeout,
+        )
+        .unwrap();
+
+        let mut bursts = Vec::new();
+        let start = Instant::now();
+        while bursts.len() < 20 && start.elapsed() < Duration::from_secs(5) {
+            bursts.extend(adis.expect_burst().unwrap());
+        }
+
+        end.store(true, Ordering::Relaxed);
+        device.join().unwrap();
+
+        assert!(bursts.len() >= 20);
+        assert!(bursts.iter().all(|b| b.sensor == 0 && !b.data.corrupted));
+        assert!(bursts
+            .windows(2)
+            .all(|w| w[1].data.data_cntr == w[0].data.data_cntr.wrapping_add(1)));
+    }
+}
@@ -0,0 +1,111 @@
+use std::ffi::CStr;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::mem::MaybeUninit;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Pseudo-terminal pair, the device talks through the master side and hosts open the slave path as serial port.
+pub struct Pty {
+    master: File,
+    // kept open so the master does not hang up when the host closes the port
+    _slave: OwnedFd,
+    path: PathBuf,
+}
+
+impl Pty {
+    pub fn open() -> io::Result<Self> {
+        let mut master = -1;
+        let mut slave = -1;
+        let res = unsafe {
+            libc::openpty(
+                &mut master,
+                &mut slave,
+                std::ptr::null_mut(),
+                std::ptr::null(),
+                std::ptr::null(),
+            )
+        };
+        if res != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let master = unsafe { File::from_raw_fd(master) };
+        let slave = unsafe { OwnedFd::from_raw_fd(slave) };
+
+        // raw mode, the line discipline must not echo or translate the frames
+        unsafe {
+            let mut termios = MaybeUninit::uninit();
+            if libc::tcgetattr(slave.as_raw_fd(), termios.as_mut_ptr()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut termios = termios.assume_init();
+            libc::cfmakeraw(&mut termios);
+            if libc::tcsetattr(slave.as_raw_fd(), libc::TCSANOW, &termios) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+
+            let flags = libc::fcntl(master.as_raw_fd(), libc::F_GETFL);
+            if flags < 0
+                || libc::fcntl(master.as_raw_fd(), libc::F_SETFL, flags | libc::O_NONBLOCK) < 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        let mut name = [0; 128];
+        let res = unsafe { libc::ttyname_r(slave.as_raw_fd(), name.as_mut_ptr(), name.len()) };
+        if res != 0 {
+            return Err(io::Error::from_raw_os_error(res));
+        }
+        let path = unsafe { CStr::from_ptr(name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+            .into();
+
+        return Ok(Self {
+            master,
+            _slave: slave,
+            path,
+        });
+    }
+
+    /// Path of the serial port to be opened by the host.
+    pub fn path(&self) -> &Path {
+        return &self.path;
+    }
+
+    /// Waits until the host sends something or the timeout passes.
+    pub fn wait_readable(&self, timeout: Duration) -> io::Result<bool> {
+        let mut fds = libc::pollfd {
+            fd: self.master.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        let timeout_ms = timeout.as_micros().div_ceil(1000).min(i32::MAX as u128) as i32;
+
+        return match unsafe { libc::poll(&mut fds, 1, timeout_ms) } {
+            r if r < 0 => match io::Error::last_os_error() {
+                e if e.kind() == io::ErrorKind::Interrupted => Ok(false),
+                e => Err(e),
+            },
+            r => Ok(r > 0 && fds.revents & libc::POLLIN != 0),
+        };
+    }
+}
+
+impl Read for Pty {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return self.master.read(buf);
+    }
+}
+
+impl Write for Pty {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        return self.master.write(buf);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.master.flush();
+    }
+}
@@ -10,6 +10,9 @@ use sensor_msgs::msg::Temperature;
 use driver;
 use driver::protocol::adis;
 
+const STREAM_CAPACITY: usize = 64;
+const CONTEXT_CHECK_PERIOD: driver::Duration = driver::Duration::from_millis(100);
+
 fn main() {
     let context = rclrs::Context::new(env::args())
         .expect("ROS2 ADIS IMU: Could not initialize context.");
@@ -39,48 +42,52 @@ fn main() {
     adis.send_config(driver::protocol::cfg::CFG::BurstEn(true))
         .expect("ROS2 ADIS IMU: Could not enable burst.");
 
-    while context.ok() {
-        let messages = adis.expect_burst().expect("ROS2 ADIS IMU: There was error while reading.");
+    let mut stream = driver::AdisStream::new(adis, STREAM_CAPACITY, driver::OverflowPolicy::DropOldest);
 
-        for m in messages {
-            if m.corrupted {
-                continue;
-            }
+    while context.ok() {
+        let Some(m) = stream.recv_timeout(CONTEXT_CHECK_PERIOD) else {
+            continue;
+        };
+        let m = m.expect("ROS2 ADIS IMU: There was error while reading.");
+
+        // the topics carry the first sensor only
+        if m.sensor != 0 || m.data.corrupted {
+            continue;
+        }
 
-            let mut imu_message = Imu::default();
-            let mut temp_message = Temperature::default();
-
-            match m.data {
-                adis::Sel::Sel0 {
-                    x_gyro,
-                    y_gyro,
-                    z_gyro,
-                    x_accl,
-                    y_accl,
-                    z_accl,
-                } => {
-                    imu_message.angular_velocity.x = x_gyro.get::<adis::radian_per_second>();
-                    imu_message.angular_velocity.y = y_gyro.get::<adis::radian_per_second>();
-                    imu_message.angular_velocity.z = z_gyro.get::<adis::radian_per_second>();
-
-                    imu_message.linear_acceleration.x = x_accl.get::<adis::meter_per_second_squared>();
-                    imu_message.linear_acceleration.y = y_accl.get::<adis::meter_per_second_squared>();
-                    imu_message.linear_acceleration.z = z_accl.get::<adis::meter_per_second_squared>();
-                }
-                _ => {}
+        let mut imu_message = Imu::default();
+        let mut temp_message = Temperature::default();
+
+        match m.data.data {
+            adis::Sel::Sel0 {
+                x_gyro,
+                y_gyro,
+                z_gyro,
+                x_accl,
+                y_accl,
+                z_accl,
+            } => {
+                imu_message.angular_velocity.x = x_gyro.get::<adis::radian_per_second>();
+                imu_message.angular_velocity.y = y_gyro.get::<adis::radian_per_second>();
+                imu_message.angular_velocity.z = z_gyro.get::<adis::radian_per_second>();
+
+                imu_message.linear_acceleration.x = x_accl.get::<adis::meter_per_second_squared>();
+                imu_message.linear_acceleration.y = y_accl.get::<adis::meter_per_second_squared>();
+                imu_message.linear_acceleration.z = z_accl.get::<adis::meter_per_second_squared>();
             }
+            _ => {}
+        }
 
-            temp_message.temperature = m.temp.get::<adis::degree_celsius>();
+        temp_message.temperature = m.data.temp.get::<adis::degree_celsius>();
 
-            publisher_imu
-                .publish(imu_message)
-                .expect("ROS2 ADIS IMU: Could not publish imu message.");
+        publisher_imu
+            .publish(imu_message)
+            .expect("ROS2 ADIS IMU: Could not publish imu message.");
 
-            publisher_temp
-                .publish(temp_message)
-                .expect("ROS2 ADIS IMU: Could not publish temp message.");
-        }
+        publisher_temp
+            .publish(temp_message)
+            .expect("ROS2 ADIS IMU: Could not publish temp message.");
     }
 
-    adis.send_restart().ok();
+    stream.stop().send_restart().ok();
 }